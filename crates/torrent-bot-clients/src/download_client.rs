@@ -0,0 +1,112 @@
+use crate::qbittorrent::QBittorrentClientError;
+use crate::transmission::TransmissionClientError;
+
+#[derive(Debug)]
+pub enum RemoveStrategy {
+    KeepLocalData,
+    DeleteLocalData,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TorrentId {
+    Id(i64),
+    Hash(String),
+}
+
+/// Torrent returned by [`DownloadClient::add`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddedTorrent {
+    pub id: TorrentId,
+    /// The client had the torrent before, so it wasn't added and is left as it was.
+    pub is_duplicate: bool,
+}
+
+#[derive(Debug)]
+pub struct TorrentFile {
+    pub index: usize,
+    pub name: String,
+    pub length: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct TorrentStatus {
+    pub is_finished: bool,
+    /// Bytes left to download for the wanted files.
+    pub left_until_done: u64,
+    pub download_dir: Option<String>,
+    pub labels: Vec<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DownloadClientError {
+    #[error("Error happened in transmission client: {0}")]
+    Transmission(#[from] TransmissionClientError),
+    #[error("Error happened in qBittorrent client: {0}")]
+    QBittorrent(#[from] QBittorrentClientError),
+}
+
+pub type DownloadClientResult<T> = Result<T, DownloadClientError>;
+
+/// Torrent client the runner downloads topics with.
+///
+/// Paths passed to [`DownloadClient::add`] and [`DownloadClient::set_location`]
+/// are relative to the download directory the client was created with.
+#[async_trait::async_trait]
+pub trait DownloadClient: Send + Sync {
+    /// Adds torrent in paused state, so files can be selected and free space can be
+    /// checked before anything is downloaded. Use [`DownloadClient::start`] to start it.
+    async fn add(
+        &self,
+        torrent_file_content: Vec<u8>,
+        path: &str,
+    ) -> DownloadClientResult<AddedTorrent>;
+
+    /// Starts torrent. Does nothing in dry run mode, so torrents stay paused.
+    async fn start(&self, torrent_id: &TorrentId) -> DownloadClientResult<()>;
+
+    async fn remove(
+        &self,
+        torrent_id: &TorrentId,
+        remove_strategy: RemoveStrategy,
+    ) -> DownloadClientResult<()>;
+
+    /// Returns `None` if the client doesn't know the torrent.
+    async fn get_status(
+        &self,
+        torrent_id: &TorrentId,
+    ) -> DownloadClientResult<Option<TorrentStatus>>;
+
+    /// Moves torrent data to another directory.
+    async fn set_location(&self, torrent_id: &TorrentId, path: &str) -> DownloadClientResult<()>;
+
+    /// Replaces torrent labels.
+    async fn set_labels(
+        &self,
+        torrent_id: &TorrentId,
+        labels: Vec<String>,
+    ) -> DownloadClientResult<()>;
+
+    async fn get_files(&self, torrent_id: &TorrentId) -> DownloadClientResult<Vec<TorrentFile>>;
+
+    /// Renames file or directory at `path` inside the torrent to `name`, on disk as well.
+    /// Only the last path component changes.
+    async fn rename_path(
+        &self,
+        torrent_id: &TorrentId,
+        path: &str,
+        name: &str,
+    ) -> DownloadClientResult<()>;
+
+    /// Rechecks local data of the torrent.
+    async fn verify(&self, torrent_id: &TorrentId) -> DownloadClientResult<()>;
+
+    async fn set_files_wanted(
+        &self,
+        torrent_id: &TorrentId,
+        files_wanted: Vec<usize>,
+        files_unwanted: Vec<usize>,
+    ) -> DownloadClientResult<()>;
+
+    /// Returns free space in the download directory.
+    async fn get_free_space(&self) -> DownloadClientResult<u64>;
+}
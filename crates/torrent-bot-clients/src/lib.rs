@@ -1,4 +1,8 @@
+pub mod download_client;
+pub mod qbittorrent;
 pub mod telegram;
 pub mod toloka;
+pub mod torrent_file;
 pub mod transmission;
 mod transmission_extensions;
+pub mod transmission_session;
@@ -0,0 +1,789 @@
+use std::time::Duration;
+
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use tracing::{debug, instrument};
+
+use crate::download_client::{
+    AddedTorrent, DownloadClient, DownloadClientResult, RemoveStrategy, TorrentFile, TorrentId,
+    TorrentStatus,
+};
+use crate::torrent_file::info_hash;
+
+/// qBittorrent adds torrents in the background, so an added torrent is polled for
+/// at this interval, at most [`ADD_POLL_ATTEMPTS`] times.
+const ADD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const ADD_POLL_ATTEMPTS: usize = 20;
+
+#[derive(Clone)]
+pub struct QBittorrentClient {
+    client: Client,
+    url: String,
+    username: String,
+    password: String,
+    download_dir: Option<String>,
+    dry_run: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QBittorrentClientError {
+    #[error("Invalid login or password")]
+    Unauthorized,
+    #[error("Unexpected status code: {0}")]
+    Status(StatusCode),
+    #[error("Unable to perform http request: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Unable to read info hash from torrent file")]
+    InvalidTorrentFile,
+    #[error("Torrent was not added in time")]
+    NotAdded,
+    #[error("Torrents can only be referenced by hash")]
+    UnsupportedTorrentId,
+    #[error("Missing download dir")]
+    MissingDownloadDir,
+}
+
+pub type QBittorrentClientResult<T> = Result<T, QBittorrentClientError>;
+
+#[derive(Deserialize)]
+struct TorrentInfo {
+    progress: f64,
+    amount_left: i64,
+    save_path: String,
+    tags: String,
+}
+
+#[derive(Deserialize)]
+struct FileInfo {
+    name: String,
+    size: i64,
+}
+
+#[derive(Deserialize)]
+struct ServerState {
+    free_space_on_disk: i64,
+}
+
+#[derive(Deserialize)]
+struct MainData {
+    server_state: ServerState,
+}
+
+fn hash_of(torrent_id: &TorrentId) -> QBittorrentClientResult<&str> {
+    match torrent_id {
+        TorrentId::Hash(hash) => Ok(hash),
+        TorrentId::Id(_) => Err(QBittorrentClientError::UnsupportedTorrentId),
+    }
+}
+
+impl QBittorrentClient {
+    pub async fn create(
+        url: &str,
+        username: &str,
+        password: &str,
+        download_dir: Option<String>,
+        dry_run: bool,
+    ) -> QBittorrentClientResult<Self> {
+        let client = Client::builder()
+            .cookie_store(true)
+            .build()
+            .expect("Failed to create HTTP Client");
+
+        let qbittorrent_client = Self {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+            download_dir,
+            dry_run,
+        };
+
+        qbittorrent_client.login().await?;
+
+        Ok(qbittorrent_client)
+    }
+
+    fn endpoint(&self, method: &str) -> String {
+        format!("{}/api/v2/{}", self.url, method)
+    }
+
+    fn download_dir(&self) -> QBittorrentClientResult<&str> {
+        self.download_dir
+            .as_deref()
+            .ok_or(QBittorrentClientError::MissingDownloadDir)
+    }
+
+    async fn login(&self) -> QBittorrentClientResult<()> {
+        let response = self
+            .client
+            .post(self.endpoint("auth/login"))
+            .form(&[("username", &self.username), ("password", &self.password)])
+            .send()
+            .await?;
+
+        if response.status() != StatusCode::OK || response.text().await? != "Ok." {
+            return Err(QBittorrentClientError::Unauthorized);
+        }
+
+        Ok(())
+    }
+
+    /// Sends request, logging in again once if the session has expired.
+    async fn send(
+        &self,
+        request: impl Fn() -> RequestBuilder,
+    ) -> QBittorrentClientResult<Response> {
+        let mut response = request().send().await?;
+
+        if response.status() == StatusCode::FORBIDDEN {
+            debug!("Session expired. Logging in again...");
+            self.login().await?;
+            response = request().send().await?;
+        }
+
+        if response.status() != StatusCode::OK {
+            return Err(QBittorrentClientError::Status(response.status()));
+        }
+
+        Ok(response)
+    }
+
+    async fn post(&self, method: &str, form: &[(&str, &str)]) -> QBittorrentClientResult<()> {
+        self.send(|| self.client.post(self.endpoint(method)).form(form))
+            .await?;
+
+        Ok(())
+    }
+
+    #[instrument(err, skip(self, torrent_file_content))]
+    pub async fn add(
+        &self,
+        torrent_file_content: Vec<u8>,
+        path: &str,
+    ) -> QBittorrentClientResult<AddedTorrent> {
+        let hash =
+            info_hash(&torrent_file_content).ok_or(QBittorrentClientError::InvalidTorrentFile)?;
+        let save_path = format!("{}/{}/", self.download_dir()?, path);
+
+        let response = self
+            .send(|| {
+                let form = Form::new()
+                    .part(
+                        "torrents",
+                        Part::bytes(torrent_file_content.clone()).file_name("topic.torrent"),
+                    )
+                    .text("savepath", save_path.clone())
+                    // qBittorrent 5 renamed "paused" to "stopped".
+                    .text("paused", "true")
+                    .text("stopped", "true");
+
+                self.client
+                    .post(self.endpoint("torrents/add"))
+                    .multipart(form)
+            })
+            .await?;
+
+        let torrent_id = TorrentId::Hash(hash);
+
+        // Adding a duplicate fails, which is fine as long as the torrent is there.
+        let is_duplicate = response.text().await? != "Ok.";
+        self.wait_for_torrent(&torrent_id).await?;
+
+        Ok(AddedTorrent {
+            id: torrent_id,
+            is_duplicate,
+        })
+    }
+
+    /// Waits until the client knows the torrent, as adding completes after the response.
+    async fn wait_for_torrent(&self, torrent_id: &TorrentId) -> QBittorrentClientResult<()> {
+        for attempt in 0..ADD_POLL_ATTEMPTS {
+            if attempt > 0 {
+                actix_rt::time::sleep(ADD_POLL_INTERVAL).await;
+            }
+
+            if self.get_status(torrent_id).await?.is_some() {
+                return Ok(());
+            }
+        }
+
+        Err(QBittorrentClientError::NotAdded)
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn start(&self, torrent_id: &TorrentId) -> QBittorrentClientResult<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let hashes = hash_of(torrent_id)?;
+
+        // qBittorrent 5 renamed "resume" to "start".
+        match self.post("torrents/start", &[("hashes", hashes)]).await {
+            Err(QBittorrentClientError::Status(StatusCode::NOT_FOUND)) => {
+                self.post("torrents/resume", &[("hashes", hashes)]).await
+            }
+            result => result,
+        }
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn remove(
+        &self,
+        torrent_id: &TorrentId,
+        remove_strategy: RemoveStrategy,
+    ) -> QBittorrentClientResult<()> {
+        let delete_files = match remove_strategy {
+            RemoveStrategy::KeepLocalData => "false",
+            RemoveStrategy::DeleteLocalData => "true",
+        };
+
+        self.post(
+            "torrents/delete",
+            &[
+                ("hashes", hash_of(torrent_id)?),
+                ("deleteFiles", delete_files),
+            ],
+        )
+        .await
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn get_status(
+        &self,
+        torrent_id: &TorrentId,
+    ) -> QBittorrentClientResult<Option<TorrentStatus>> {
+        let hashes = hash_of(torrent_id)?;
+        let torrents: Vec<TorrentInfo> = self
+            .send(|| {
+                self.client
+                    .get(self.endpoint("torrents/info"))
+                    .query(&[("hashes", hashes)])
+            })
+            .await?
+            .json()
+            .await?;
+
+        Ok(torrents.into_iter().next().map(|torrent| TorrentStatus {
+            is_finished: torrent.progress >= 1.0,
+            left_until_done: torrent.amount_left.max(0) as u64,
+            download_dir: Some(torrent.save_path),
+            labels: torrent
+                .tags
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(String::from)
+                .collect(),
+        }))
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn set_location(
+        &self,
+        torrent_id: &TorrentId,
+        path: &str,
+    ) -> QBittorrentClientResult<()> {
+        let location = format!("{}/{}/", self.download_dir()?, path);
+
+        self.post(
+            "torrents/setLocation",
+            &[("hashes", hash_of(torrent_id)?), ("location", &location)],
+        )
+        .await
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn set_labels(
+        &self,
+        torrent_id: &TorrentId,
+        labels: Vec<String>,
+    ) -> QBittorrentClientResult<()> {
+        let hashes = hash_of(torrent_id)?;
+
+        // Without "tags" all tags are removed from the torrent.
+        self.post("torrents/removeTags", &[("hashes", hashes)])
+            .await?;
+
+        if labels.is_empty() {
+            return Ok(());
+        }
+
+        self.post(
+            "torrents/addTags",
+            &[("hashes", hashes), ("tags", &labels.join(","))],
+        )
+        .await
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn get_files(
+        &self,
+        torrent_id: &TorrentId,
+    ) -> QBittorrentClientResult<Vec<TorrentFile>> {
+        let hash = hash_of(torrent_id)?;
+        let files: Vec<FileInfo> = self
+            .send(|| {
+                self.client
+                    .get(self.endpoint("torrents/files"))
+                    .query(&[("hash", hash)])
+            })
+            .await?
+            .json()
+            .await?;
+
+        Ok(files
+            .into_iter()
+            .enumerate()
+            .map(|(index, file)| TorrentFile {
+                index,
+                name: file.name,
+                length: file.size.max(0) as u64,
+            })
+            .collect())
+    }
+
+    /// qBittorrent renames files and folders with separate methods,
+    /// so the torrent's files tell which one `path` is.
+    #[instrument(err, skip(self))]
+    pub async fn rename_path(
+        &self,
+        torrent_id: &TorrentId,
+        path: &str,
+        name: &str,
+    ) -> QBittorrentClientResult<()> {
+        let hash = hash_of(torrent_id)?;
+        let folder_prefix = format!("{}/", path);
+        let is_folder = self
+            .get_files(torrent_id)
+            .await?
+            .iter()
+            .any(|file| file.name.starts_with(&folder_prefix));
+        let new_path = match path.rsplit_once('/') {
+            Some((parent, _)) => format!("{}/{}", parent, name),
+            None => name.to_string(),
+        };
+
+        self.post(
+            if is_folder {
+                "torrents/renameFolder"
+            } else {
+                "torrents/renameFile"
+            },
+            &[("hash", hash), ("oldPath", path), ("newPath", &new_path)],
+        )
+        .await
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn verify(&self, torrent_id: &TorrentId) -> QBittorrentClientResult<()> {
+        self.post("torrents/recheck", &[("hashes", hash_of(torrent_id)?)])
+            .await
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn set_files_wanted(
+        &self,
+        torrent_id: &TorrentId,
+        files_wanted: Vec<usize>,
+        files_unwanted: Vec<usize>,
+    ) -> QBittorrentClientResult<()> {
+        let hash = hash_of(torrent_id)?;
+
+        for (files, priority) in [(files_wanted, "1"), (files_unwanted, "0")] {
+            if files.is_empty() {
+                continue;
+            }
+
+            let ids = files
+                .iter()
+                .map(|index| index.to_string())
+                .collect::<Vec<_>>()
+                .join("|");
+
+            self.post(
+                "torrents/filePrio",
+                &[("hash", hash), ("id", &ids), ("priority", priority)],
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns free space on the disk of the default save path,
+    /// which is the only disk qBittorrent reports.
+    #[instrument(err, skip(self))]
+    pub async fn get_free_space(&self) -> QBittorrentClientResult<u64> {
+        let main_data: MainData = self
+            .send(|| self.client.get(self.endpoint("sync/maindata")))
+            .await?
+            .json()
+            .await?;
+
+        Ok(main_data.server_state.free_space_on_disk.max(0) as u64)
+    }
+}
+
+#[async_trait::async_trait]
+impl DownloadClient for QBittorrentClient {
+    async fn add(
+        &self,
+        torrent_file_content: Vec<u8>,
+        path: &str,
+    ) -> DownloadClientResult<AddedTorrent> {
+        Ok(QBittorrentClient::add(self, torrent_file_content, path).await?)
+    }
+
+    async fn start(&self, torrent_id: &TorrentId) -> DownloadClientResult<()> {
+        Ok(QBittorrentClient::start(self, torrent_id).await?)
+    }
+
+    async fn remove(
+        &self,
+        torrent_id: &TorrentId,
+        remove_strategy: RemoveStrategy,
+    ) -> DownloadClientResult<()> {
+        Ok(QBittorrentClient::remove(self, torrent_id, remove_strategy).await?)
+    }
+
+    async fn get_status(
+        &self,
+        torrent_id: &TorrentId,
+    ) -> DownloadClientResult<Option<TorrentStatus>> {
+        Ok(QBittorrentClient::get_status(self, torrent_id).await?)
+    }
+
+    async fn set_location(&self, torrent_id: &TorrentId, path: &str) -> DownloadClientResult<()> {
+        Ok(QBittorrentClient::set_location(self, torrent_id, path).await?)
+    }
+
+    async fn set_labels(
+        &self,
+        torrent_id: &TorrentId,
+        labels: Vec<String>,
+    ) -> DownloadClientResult<()> {
+        Ok(QBittorrentClient::set_labels(self, torrent_id, labels).await?)
+    }
+
+    async fn get_files(&self, torrent_id: &TorrentId) -> DownloadClientResult<Vec<TorrentFile>> {
+        Ok(QBittorrentClient::get_files(self, torrent_id).await?)
+    }
+
+    async fn rename_path(
+        &self,
+        torrent_id: &TorrentId,
+        path: &str,
+        name: &str,
+    ) -> DownloadClientResult<()> {
+        Ok(QBittorrentClient::rename_path(self, torrent_id, path, name).await?)
+    }
+
+    async fn verify(&self, torrent_id: &TorrentId) -> DownloadClientResult<()> {
+        Ok(QBittorrentClient::verify(self, torrent_id).await?)
+    }
+
+    async fn set_files_wanted(
+        &self,
+        torrent_id: &TorrentId,
+        files_wanted: Vec<usize>,
+        files_unwanted: Vec<usize>,
+    ) -> DownloadClientResult<()> {
+        Ok(
+            QBittorrentClient::set_files_wanted(self, torrent_id, files_wanted, files_unwanted)
+                .await?,
+        )
+    }
+
+    async fn get_free_space(&self) -> DownloadClientResult<u64> {
+        Ok(QBittorrentClient::get_free_space(self).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    const TORRENT: &[u8] = b"d4:infod6:lengthi1e4:name1:a12:piece lengthi1e6:pieces0:ee";
+
+    async fn create_client(server: &MockServer) -> QBittorrentClient {
+        Mock::given(method("POST"))
+            .and(path("/api/v2/auth/login"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("Ok."))
+            .mount(server)
+            .await;
+
+        QBittorrentClient::create(
+            &server.uri(),
+            "admin",
+            "secret",
+            Some("/data".to_string()),
+            false,
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn mount_torrent_info(server: &MockServer) {
+        Mock::given(method("GET"))
+            .and(path("/api/v2/torrents/info"))
+            .and(query_param("hashes", info_hash(TORRENT).unwrap()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{
+                "progress": 0.0,
+                "amount_left": 1,
+                "save_path": "/data/Series/",
+                "tags": "",
+            }])))
+            .mount(server)
+            .await;
+    }
+
+    #[actix_rt::test]
+    async fn test_login_with_invalid_credentials() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/api/v2/auth/login"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("Fails."))
+            .mount(&server)
+            .await;
+
+        let result = QBittorrentClient::create(&server.uri(), "admin", "wrong", None, false).await;
+
+        assert!(matches!(result, Err(QBittorrentClientError::Unauthorized)));
+    }
+
+    #[actix_rt::test]
+    async fn test_add_returns_info_hash() {
+        let server = MockServer::start().await;
+        let client = create_client(&server).await;
+
+        Mock::given(method("POST"))
+            .and(path("/api/v2/torrents/add"))
+            .and(body_string_contains("/data/Series/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("Ok."))
+            .expect(1)
+            .mount(&server)
+            .await;
+        mount_torrent_info(&server).await;
+
+        let added = client.add(TORRENT.to_vec(), "Series").await.unwrap();
+
+        assert_eq!(
+            added,
+            AddedTorrent {
+                id: TorrentId::Hash(info_hash(TORRENT).unwrap()),
+                is_duplicate: false,
+            }
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_add_waits_until_torrent_appears() {
+        let server = MockServer::start().await;
+        let client = create_client(&server).await;
+
+        Mock::given(method("POST"))
+            .and(path("/api/v2/torrents/add"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("Ok."))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v2/torrents/info"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        mount_torrent_info(&server).await;
+
+        let added = client.add(TORRENT.to_vec(), "Series").await.unwrap();
+
+        assert!(!added.is_duplicate);
+    }
+
+    #[actix_rt::test]
+    async fn test_add_of_duplicate() {
+        let server = MockServer::start().await;
+        let client = create_client(&server).await;
+
+        Mock::given(method("POST"))
+            .and(path("/api/v2/torrents/add"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("Fails."))
+            .mount(&server)
+            .await;
+        mount_torrent_info(&server).await;
+
+        let added = client.add(TORRENT.to_vec(), "Series").await.unwrap();
+
+        assert!(added.is_duplicate);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_status() {
+        let server = MockServer::start().await;
+        let client = create_client(&server).await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v2/torrents/info"))
+            .and(query_param("hashes", "abc"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{
+                "progress": 1.0,
+                "amount_left": 0,
+                "save_path": "/data/Movies/",
+                "tags": "torrent-bot, movies"
+            }])))
+            .mount(&server)
+            .await;
+
+        let status = client
+            .get_status(&TorrentId::Hash("abc".to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(status.is_finished);
+        assert_eq!(status.left_until_done, 0);
+        assert_eq!(status.download_dir.as_deref(), Some("/data/Movies/"));
+        assert_eq!(status.labels, vec!["torrent-bot", "movies"]);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_status_of_unknown_torrent() {
+        let server = MockServer::start().await;
+        let client = create_client(&server).await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v2/torrents/info"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+
+        let status = client
+            .get_status(&TorrentId::Hash("abc".to_string()))
+            .await
+            .unwrap();
+
+        assert!(status.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_start_falls_back_to_resume() {
+        let server = MockServer::start().await;
+        let client = create_client(&server).await;
+
+        Mock::given(method("POST"))
+            .and(path("/api/v2/torrents/start"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v2/torrents/resume"))
+            .and(body_string_contains("hashes=abc"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client
+            .start(&TorrentId::Hash("abc".to_string()))
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_logs_in_again_when_session_expired() {
+        let server = MockServer::start().await;
+        let client = create_client(&server).await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v2/sync/maindata"))
+            .respond_with(ResponseTemplate::new(403))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v2/sync/maindata"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "server_state": { "free_space_on_disk": 1024 }
+            })))
+            .mount(&server)
+            .await;
+
+        assert_eq!(client.get_free_space().await.unwrap(), 1024);
+        assert_eq!(
+            server
+                .received_requests()
+                .await
+                .unwrap()
+                .iter()
+                .filter(|r| r.url.path() == "/api/v2/auth/login")
+                .count(),
+            2
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_set_files_wanted() {
+        let server = MockServer::start().await;
+        let client = create_client(&server).await;
+
+        Mock::given(method("POST"))
+            .and(path("/api/v2/torrents/filePrio"))
+            .and(body_string_contains("id=0%7C2"))
+            .and(body_string_contains("priority=1"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v2/torrents/filePrio"))
+            .and(body_string_contains("id=1"))
+            .and(body_string_contains("priority=0"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client
+            .set_files_wanted(&TorrentId::Hash("abc".to_string()), vec![0, 2], vec![1])
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_rename_path_of_folder() {
+        let server = MockServer::start().await;
+        let client = create_client(&server).await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v2/torrents/files"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "name": "Show S02 (1-4)/E01.mkv", "size": 10 }
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v2/torrents/renameFolder"))
+            .and(body_string_contains("oldPath=Show+S02+%281-4%29"))
+            .and(body_string_contains("newPath=Show+S02+%281-6%29"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client
+            .rename_path(
+                &TorrentId::Hash("abc".to_string()),
+                "Show S02 (1-4)",
+                "Show S02 (1-6)",
+            )
+            .await
+            .unwrap();
+    }
+}
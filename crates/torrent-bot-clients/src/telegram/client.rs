@@ -3,12 +3,17 @@ use std::sync::Arc;
 use teloxide::{Bot, macros, RequestError};
 use teloxide::dispatching::dialogue::GetChatId;
 use teloxide::prelude::*;
-use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile, ParseMode};
 use teloxide::utils::command::BotCommands;
 use tracing::{error, warn};
 
 use crate::telegram::BotCommandHandler;
 
+/// Telegram limits messages to 4096 characters.
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+/// Telegram limits photo captions to 1024 characters.
+const MAX_CAPTION_LENGTH: usize = 1024;
+
 #[derive(Clone)]
 pub struct TelegramBotClient {
     bot: Bot,
@@ -25,6 +30,8 @@ enum BotCommand {
     Help,
     #[command(description = "search for a topic.")]
     Search { query: String },
+    #[command(description = "show what happened in a period, 12h by default, e.g. /events 7d.")]
+    Events { period: String },
 }
 
 pub struct ActionButton {
@@ -88,6 +95,9 @@ impl TelegramBotClient {
                                         BotCommand::Search { query } => {
                                             handler.handle_search_command(&query).await;
                                         }
+                                        BotCommand::Events { period } => {
+                                            handler.handle_events_command(&period).await;
+                                        }
                                     }
 
                                     Ok::<(), RequestError>(())
@@ -110,21 +120,80 @@ impl TelegramBotClient {
         }
     }
 
-    pub async fn send_message_with_action_buttons(&self, text: &str, buttons: Vec<ActionButton>) {
-        let markup = InlineKeyboardMarkup::new(
-            buttons
-                .into_iter()
-                .map(|b| vec![InlineKeyboardButton::callback(b.text, b.action)])
-                .collect::<Vec<_>>(),
-        );
+    /// Sends a message formatted with Telegram's HTML style.
+    /// Text inserted into it should be escaped with [`crate::telegram::html::escape`].
+    pub async fn send_html_message(&self, html: &str) {
+        if let Err(error) = self
+            .bot
+            .send_message(self.chat_id, html)
+            .parse_mode(ParseMode::Html)
+            .await
+        {
+            error!(?error, "Failed to send message to telegram bot");
+        }
+    }
+
+    /// Sends a photo with an HTML caption. The caption is sent as a message instead
+    /// when it's too long, or when telegram can't get the photo.
+    pub async fn send_photo(&self, photo_url: &str, html_caption: &str) {
+        if html_caption.chars().count() > MAX_CAPTION_LENGTH {
+            return self.send_html_message(html_caption).await;
+        }
+
+        let photo_url = match reqwest::Url::parse(photo_url) {
+            Ok(photo_url) => photo_url,
+            Err(error) => {
+                warn!(?error, photo_url, "Invalid photo url");
+                return self.send_html_message(html_caption).await;
+            }
+        };
+
+        if let Err(error) = self
+            .bot
+            .send_photo(self.chat_id, InputFile::url(photo_url))
+            .caption(html_caption)
+            .parse_mode(ParseMode::Html)
+            .await
+        {
+            warn!(?error, "Failed to send photo to telegram bot");
+            self.send_html_message(html_caption).await;
+        }
+    }
 
+    pub async fn send_message_with_action_buttons(&self, text: &str, buttons: Vec<ActionButton>) {
         if let Err(error) = self
             .bot
             .send_message(self.chat_id, text)
-            .reply_markup(markup)
+            .reply_markup(action_buttons_markup(buttons))
+            .await
+        {
+            error!(?error, "Failed to send message to telegram bot");
+        }
+    }
+
+    /// Sends a message formatted with Telegram's HTML style, with a button per action.
+    pub async fn send_html_message_with_action_buttons(
+        &self,
+        html: &str,
+        buttons: Vec<ActionButton>,
+    ) {
+        if let Err(error) = self
+            .bot
+            .send_message(self.chat_id, html)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(action_buttons_markup(buttons))
             .await
         {
             error!(?error, "Failed to send message to telegram bot");
         }
     }
 }
+
+fn action_buttons_markup(buttons: Vec<ActionButton>) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(
+        buttons
+            .into_iter()
+            .map(|b| vec![InlineKeyboardButton::callback(b.text, b.action)])
+            .collect::<Vec<_>>(),
+    )
+}
@@ -1,5 +1,7 @@
 pub use self::client::*;
 pub use self::traits::*;
+/// Helpers to build messages sent with [`TelegramBotClient::send_html_message`].
+pub use teloxide::utils::html;
 
 mod client;
 mod traits;
@@ -1,14 +1,15 @@
+use reqwest::header::LOCATION;
 use reqwest::redirect::Policy;
-use reqwest::{Client, StatusCode};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
 use serde::Serialize;
 use serde_json::json;
-use tracing::warn;
+use tracing::{debug, warn};
 
-use crate::toloka::types::{DownloadMeta, Topic, TopicMeta};
+use crate::toloka::types::{DownloadMeta, Topic, TopicMeta, WatchedTopics};
 
 const TOLOKA_HOST: &str = "https://toloka.to";
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 struct LoginForm {
     username: String,
     password: String,
@@ -21,6 +22,8 @@ struct LoginForm {
 #[derive(Clone)]
 pub struct TolokaClient {
     client: Client,
+    host: String,
+    login_form: LoginForm,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -36,14 +39,27 @@ pub enum TolokaClientError {
 pub type TolokaClientResult<T> = Result<T, TolokaClientError>;
 
 impl TolokaClient {
+    /// Link to the topic page, e.g. `https://toloka.to/t679577`.
+    pub fn topic_url(topic_id: &str) -> String {
+        format!("{}/{}", TOLOKA_HOST, topic_id)
+    }
+
     pub async fn create(username: &str, password: &str) -> TolokaClientResult<TolokaClient> {
+        Self::create_for_host(TOLOKA_HOST, username, password).await
+    }
+
+    async fn create_for_host(
+        host: &str,
+        username: &str,
+        password: &str,
+    ) -> TolokaClientResult<TolokaClient> {
         let client = Client::builder()
             .redirect(Policy::none())
             .cookie_store(true)
             .build()
             .expect("Failed to create HTTP Client");
 
-        let form = LoginForm {
+        let login_form = LoginForm {
             username: username.to_string(),
             password: password.to_string(),
             autologin: String::from("on"),
@@ -52,9 +68,21 @@ impl TolokaClient {
             login: String::from("Вхід"),
         };
 
-        let response = client
-            .post(format!("{}/login.php", TOLOKA_HOST))
-            .form(&form)
+        let toloka_client = Self {
+            client,
+            host: host.to_string(),
+            login_form,
+        };
+        toloka_client.login().await?;
+
+        Ok(toloka_client)
+    }
+
+    async fn login(&self) -> TolokaClientResult<()> {
+        let response = self
+            .client
+            .post(format!("{}/login.php", self.host))
+            .form(&self.login_form)
             .send()
             .await?;
 
@@ -62,125 +90,276 @@ impl TolokaClient {
             return Err(TolokaClientError::Unauthorized);
         }
 
-        Ok(Self { client })
+        Ok(())
     }
 
-    pub async fn download(&self, download_id: &str) -> TolokaClientResult<Vec<u8>> {
-        let response = self
-            .client
-            .get(format!("{}/download.php?id={}", TOLOKA_HOST, download_id))
-            .send()
-            .await?;
+    /// Sends request, logging in again once if the session has expired.
+    async fn send(&self, request: impl Fn() -> RequestBuilder) -> TolokaClientResult<Response> {
+        let mut response = request().send().await?;
+
+        if is_session_expired(&response) {
+            debug!("Session expired. Logging in again...");
+            self.login().await?;
+            response = request().send().await?;
+
+            if is_session_expired(&response) {
+                return Err(TolokaClientError::Unauthorized);
+            }
+        }
 
         if response.status() != StatusCode::OK {
             return Err(TolokaClientError::Status(response.status()));
         }
 
-        Ok(response.bytes().await?.to_vec())
+        Ok(response)
     }
 
-    async fn get_watched_topics_meta(&self) -> TolokaClientResult<Vec<TopicMeta>> {
+    /// Fetches page, logging in again once if the login page is returned instead.
+    async fn get_page(&self, request: impl Fn() -> RequestBuilder) -> TolokaClientResult<String> {
+        let document = self.send(&request).await?.text().await?;
+
+        if is_logged_in(&document) {
+            return Ok(document);
+        }
+
+        debug!("Got login page. Logging in again...");
+        self.login().await?;
+
+        let document = self.send(&request).await?.text().await?;
+
+        match is_logged_in(&document) {
+            true => Ok(document),
+            false => Err(TolokaClientError::Unauthorized),
+        }
+    }
+
+    pub async fn download(&self, download_id: &str) -> TolokaClientResult<Vec<u8>> {
         let response = self
-            .client
-            .get(format!("{}/watched_topics.php", TOLOKA_HOST))
-            .send()
+            .send(|| {
+                self.client
+                    .get(format!("{}/download.php?id={}", self.host, download_id))
+            })
             .await?;
 
-        if response.status() != StatusCode::OK {
-            return Err(TolokaClientError::Status(response.status()));
-        }
+        Ok(response.bytes().await?.to_vec())
+    }
 
-        let document = response.text().await?;
+    async fn get_watched_topics_meta(&self) -> TolokaClientResult<Vec<TopicMeta>> {
+        let document = self
+            .get_page(|| self.client.get(format!("{}/watched_topics.php", self.host)))
+            .await?;
         let topics_meta = super::parsers::parse_watched_topics_meta(&document);
 
         Ok(topics_meta)
     }
 
     async fn get_download_meta(&self, topic_id: &str) -> TolokaClientResult<Option<DownloadMeta>> {
-        let response = self
-            .client
-            .get(format!("{}/{}", TOLOKA_HOST, topic_id))
-            .send()
+        let document = self
+            .get_page(|| self.client.get(format!("{}/{}", self.host, topic_id)))
             .await?;
-
-        if response.status() != StatusCode::OK {
-            return Err(TolokaClientError::Status(response.status()));
-        }
-
-        let document = response.text().await?;
         let download_meta = super::parsers::parse_download_meta(&document);
 
         Ok(download_meta)
     }
 
-    pub async fn get_watched_topics(&self) -> TolokaClientResult<Vec<Topic>> {
+    /// Fetches the page of every watched topic. A topic whose page can't be fetched
+    /// or has no download doesn't stop the others, and is returned as unknown.
+    pub async fn get_watched_topics(&self) -> TolokaClientResult<WatchedTopics> {
         let topics_meta = self.get_watched_topics_meta().await?;
-        let mut topics = vec![];
+        let mut watched_topics = WatchedTopics {
+            topics: vec![],
+            unknown_topic_ids: vec![],
+        };
 
         for topic_meta in topics_meta.into_iter() {
-            match self.get_download_meta(&topic_meta.topic_id).await? {
-                Some(download_meta) => {
-                    topics.push(Topic {
+            match self.get_download_meta(&topic_meta.topic_id).await {
+                Ok(Some(download_meta)) => {
+                    watched_topics.topics.push(Topic {
                         topic_meta,
                         download_meta,
                     });
                 }
-                None => {
-                    warn!(?topic_meta.topic_id, "Missing download meta. Skipping...")
+                Ok(None) => {
+                    warn!(?topic_meta.topic_id, "Missing download meta. Skipping...");
+                    watched_topics.unknown_topic_ids.push(topic_meta.topic_id);
+                }
+                // Session is needed for the rest of the topics as well.
+                Err(TolokaClientError::Unauthorized) => {
+                    return Err(TolokaClientError::Unauthorized)
+                }
+                Err(error) => {
+                    warn!(?topic_meta.topic_id, ?error, "Unable to fetch topic. Skipping...");
+                    watched_topics.unknown_topic_ids.push(topic_meta.topic_id);
                 }
             }
 
             let _ = actix_rt::time::sleep(std::time::Duration::from_secs(5)).await;
         }
 
-        Ok(topics)
+        Ok(watched_topics)
     }
 
     pub async fn get_search_results_meta(&self, query: &str) -> TolokaClientResult<Vec<TopicMeta>> {
-        let response = self
-            .client
-            .get(format!("{}/tracker.php", TOLOKA_HOST))
-            .query(&json!({ "nm": query }))
-            .send()
+        let document = self
+            .get_page(|| {
+                self.client
+                    .get(format!("{}/tracker.php", self.host))
+                    .query(&json!({ "nm": query }))
+            })
             .await?;
-
-        if response.status() != StatusCode::OK {
-            return Err(TolokaClientError::Status(response.status()));
-        }
-
-        let document = response.text().await?;
         let results_meta = super::parsers::parse_search_results_meta(&document);
 
         Ok(results_meta)
     }
 
     pub async fn add_topic_to_bookmarks(&self, topic_id: &str) -> TolokaClientResult<()> {
-        let response = self
-            .client
-            .get(format!("{}/viewtopic.php", TOLOKA_HOST))
-            .query(&json!({ "t": topic_id, "watch": "topic" }))
-            .send()
-            .await?;
-
-        if response.status() != StatusCode::OK {
-            return Err(TolokaClientError::Status(response.status()));
-        }
+        self.get_page(|| {
+            self.client
+                .get(format!("{}/viewtopic.php", self.host))
+                .query(&json!({ "t": topic_id, "watch": "topic" }))
+        })
+        .await?;
 
         Ok(())
     }
 
     pub async fn remove_topic_from_bookmarks(&self, topic_id: &str) -> TolokaClientResult<()> {
-        let response = self
-            .client
-            .get(format!("{}/viewtopic.php", TOLOKA_HOST))
-            .query(&json!({ "t": topic_id, "unwatch": "topic" }))
-            .send()
-            .await?;
-
-        if response.status() != StatusCode::OK {
-            return Err(TolokaClientError::Status(response.status()));
-        }
+        self.get_page(|| {
+            self.client
+                .get(format!("{}/viewtopic.php", self.host))
+                .query(&json!({ "t": topic_id, "unwatch": "topic" }))
+        })
+        .await?;
 
         Ok(())
     }
 }
+
+/// Expired session gets redirected to the login page, or is refused.
+fn is_session_expired(response: &Response) -> bool {
+    let redirects_to_login = response.status() == StatusCode::FOUND
+        && response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .is_some_and(|location| location.contains("login.php"));
+
+    redirects_to_login
+        || matches!(
+            response.status(),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+        )
+}
+
+/// Pages of a logged in user link to logging out, the login page doesn't.
+fn is_logged_in(document: &str) -> bool {
+    document.contains("login.php?logout")
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    const WATCHED_TOPICS: &str = include_str!("./res/watched_topics.html");
+
+    fn login_redirect() -> ResponseTemplate {
+        ResponseTemplate::new(302).insert_header("Location", "index.php")
+    }
+
+    async fn create_client(server: &MockServer, logins: u64) -> TolokaClient {
+        Mock::given(method("POST"))
+            .and(path("/login.php"))
+            .respond_with(login_redirect())
+            .expect(logins)
+            .mount(server)
+            .await;
+
+        TolokaClient::create_for_host(&server.uri(), "user", "secret")
+            .await
+            .unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_login_with_invalid_credentials() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/login.php"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let result = TolokaClient::create_for_host(&server.uri(), "user", "wrong").await;
+
+        assert!(matches!(result, Err(TolokaClientError::Unauthorized)));
+    }
+
+    #[actix_rt::test]
+    async fn test_logs_in_again_when_redirected_to_login() {
+        let server = MockServer::start().await;
+        let client = create_client(&server, 2).await;
+
+        Mock::given(method("GET"))
+            .and(path("/watched_topics.php"))
+            .respond_with(
+                ResponseTemplate::new(302).insert_header("Location", "login.php?redirect=x"),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/watched_topics.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(WATCHED_TOPICS))
+            .mount(&server)
+            .await;
+
+        let topics = client.get_watched_topics_meta().await.unwrap();
+
+        assert!(!topics.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_logs_in_again_when_login_page_is_returned() {
+        let server = MockServer::start().await;
+        let client = create_client(&server, 2).await;
+
+        Mock::given(method("GET"))
+            .and(path("/watched_topics.php"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"<form action="login.php"></form>"#),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/watched_topics.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(WATCHED_TOPICS))
+            .mount(&server)
+            .await;
+
+        let topics = client.get_watched_topics_meta().await.unwrap();
+
+        assert!(!topics.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_fails_when_login_page_is_returned_after_login() {
+        let server = MockServer::start().await;
+        let client = create_client(&server, 2).await;
+
+        Mock::given(method("GET"))
+            .and(path("/watched_topics.php"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"<form action="login.php"></form>"#),
+            )
+            .mount(&server)
+            .await;
+
+        let result = client.get_watched_topics_meta().await;
+
+        assert!(matches!(result, Err(TolokaClientError::Unauthorized)));
+    }
+}
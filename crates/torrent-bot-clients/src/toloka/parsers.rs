@@ -74,12 +74,23 @@ pub(crate) fn parse_download_meta(document: &str) -> Option<DownloadMeta> {
         parse_registered_at()
     };
 
+    let poster_url = {
+        let poster_selector = Selector::parse(r#"meta[property="og:image"]"#).unwrap();
+
+        html.select(&poster_selector)
+            .next()
+            .and_then(|e| e.value().attr("content"))
+            .filter(|url| !url.is_empty())
+            .map(|url| url.to_string())
+    };
+
     html.select(&download_selector)
         .next()
         .map(|e| e.value().attr("href").unwrap_or_default().to_string())
         .map(|url| DownloadMeta {
             download_id: url.replace("download.php?id=", ""),
             registered_at: registered_at.unwrap_or_default(),
+            poster_url,
         })
 }
 
@@ -156,6 +167,10 @@ mod tests {
 
         assert_eq!(download_meta.download_id, "693501");
         assert_eq!(download_meta.registered_at, "2024-07-08 14:53");
+        assert_eq!(
+            download_meta.poster_url.as_deref(),
+            Some("https://thumb.hurtom.com/image/w250/toloka.to/photos/2406180006412760_f0_0.jpg")
+        );
     }
 
     #[test]
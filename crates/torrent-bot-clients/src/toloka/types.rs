@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Category {
     Movies,
     Series,
@@ -27,9 +27,19 @@ pub struct TopicMeta {
 pub struct DownloadMeta {
     pub registered_at: String,
     pub download_id: String,
+    /// Poster image of the topic, if the topic page has one.
+    pub poster_url: Option<String>,
 }
 
 pub struct Topic {
     pub topic_meta: TopicMeta,
     pub download_meta: DownloadMeta,
 }
+
+/// Topics of the watched topics page.
+pub struct WatchedTopics {
+    pub topics: Vec<Topic>,
+    /// Watched topics whose page couldn't be fetched or had no download,
+    /// so it's unknown whether they changed.
+    pub unknown_topic_ids: Vec<String>,
+}
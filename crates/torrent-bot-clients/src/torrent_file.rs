@@ -0,0 +1,139 @@
+//! Minimal bencode reader, just enough to compute the info hash and size of a .torrent file.
+
+/// Returns position right after the bencoded value starting at `pos`.
+fn skip_value(data: &[u8], pos: usize) -> Option<usize> {
+    match data.get(pos)? {
+        b'i' => {
+            let end = data[pos..].iter().position(|b| *b == b'e')?;
+            Some(pos + end + 1)
+        }
+        b'l' | b'd' => {
+            let mut pos = pos + 1;
+            while *data.get(pos)? != b'e' {
+                pos = skip_value(data, pos)?;
+            }
+            Some(pos + 1)
+        }
+        b'0'..=b'9' => string_bounds(data, pos).map(|(_, end)| end),
+        _ => None,
+    }
+}
+
+/// Returns bounds of the bencoded string contents starting at `pos`.
+fn string_bounds(data: &[u8], pos: usize) -> Option<(usize, usize)> {
+    let colon = pos + data[pos..].iter().position(|b| *b == b':')?;
+    let length = std::str::from_utf8(&data[pos..colon])
+        .ok()?
+        .parse::<usize>()
+        .ok()?;
+    let start = colon + 1;
+    let end = start.checked_add(length)?;
+
+    (end <= data.len()).then_some((start, end))
+}
+
+/// Returns bounds of the value under `key` in the bencoded dictionary starting at `pos`.
+fn dict_value(data: &[u8], pos: usize, key: &[u8]) -> Option<(usize, usize)> {
+    if data.get(pos)? != &b'd' {
+        return None;
+    }
+
+    let mut pos = pos + 1;
+    while *data.get(pos)? != b'e' {
+        let (key_start, key_end) = string_bounds(data, pos)?;
+        let value_end = skip_value(data, key_end)?;
+
+        if &data[key_start..key_end] == key {
+            return Some((key_end, value_end));
+        }
+
+        pos = value_end;
+    }
+
+    None
+}
+
+/// Parses the bencoded non-negative integer with the given bounds.
+fn integer(data: &[u8], (start, end): (usize, usize)) -> Option<u64> {
+    if data.get(start)? != &b'i' {
+        return None;
+    }
+
+    std::str::from_utf8(data.get(start + 1..end - 1)?)
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Computes hex encoded SHA-1 of the bencoded `info` dictionary,
+/// which is how torrent clients identify torrents.
+pub fn info_hash(data: &[u8]) -> Option<String> {
+    let (start, end) = dict_value(data, 0, b"info")?;
+
+    Some(
+        sha1_smol::Sha1::from(&data[start..end])
+            .digest()
+            .to_string(),
+    )
+}
+
+/// Sums lengths of all files in the torrent, in bytes.
+pub fn total_length(data: &[u8]) -> Option<u64> {
+    let (info, _) = dict_value(data, 0, b"info")?;
+
+    if let Some(length) = dict_value(data, info, b"length") {
+        return integer(data, length);
+    }
+
+    let (files, files_end) = dict_value(data, info, b"files")?;
+    let mut total = 0u64;
+    let mut pos = files + 1;
+    while pos < files_end - 1 {
+        total = total.checked_add(integer(data, dict_value(data, pos, b"length")?)?)?;
+        pos = skip_value(data, pos)?;
+    }
+
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_info_hash() {
+        let info = b"d6:lengthi12345e4:name8:file.mkv12:piece lengthi16384e6:pieces0:e";
+        let torrent = [
+            b"d8:announce18:http://tracker/ann4:info".as_slice(),
+            info,
+            b"e",
+        ]
+        .concat();
+
+        assert_eq!(
+            info_hash(&torrent),
+            Some(sha1_smol::Sha1::from(info).digest().to_string())
+        );
+    }
+
+    #[test]
+    fn test_total_length() {
+        let single_file = b"d4:infod6:lengthi12345e4:name8:file.mkvee";
+        let multiple_files =
+            b"d4:infod5:filesld6:lengthi100e4:pathl5:a.mkveed6:lengthi23e4:pathl5:b.srteee4:name3:diree";
+
+        assert_eq!(total_length(single_file), Some(12345));
+        assert_eq!(total_length(multiple_files), Some(123));
+        assert_eq!(total_length(b"d4:infod4:name3:diree"), None);
+        assert_eq!(total_length(b"d4:infod6:lengthi-1eee"), None);
+    }
+
+    #[test]
+    fn test_info_hash_of_malformed_data() {
+        assert_eq!(info_hash(b""), None);
+        assert_eq!(info_hash(b"not a torrent"), None);
+        assert_eq!(info_hash(b"d4:info"), None);
+        assert_eq!(info_hash(b"d8:announce99:shorte"), None);
+        assert_eq!(info_hash(b"d8:announce3:urle"), None);
+    }
+}
@@ -1,26 +1,39 @@
 use std::sync::Arc;
 
 use base64::{engine::general_purpose, Engine as _};
-use parking_lot::Mutex;
+use parking_lot::RwLock;
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
 use tracing::{debug, instrument};
-use transmission_rpc::types::{BasicAuth, RpcResponse, TorrentAddArgs, TorrentAddedOrDuplicate};
-use transmission_rpc::TransClient;
+use transmission_rpc::types::{
+    FreeSpace, Id, Torrent, TorrentAddArgs, TorrentAddedOrDuplicate, TorrentGetField,
+    TorrentSetArgs, Torrents,
+};
 
-#[derive(Debug)]
-pub enum RemoveStrategy {
-    KeepLocalData,
-    DeleteLocalData,
-}
+use crate::download_client::{
+    AddedTorrent, DownloadClient, DownloadClientResult, RemoveStrategy, TorrentFile, TorrentId,
+    TorrentStatus,
+};
+use crate::transmission_session::SessionSettings;
 
-#[derive(Debug)]
-pub enum TorrentId {
-    Id(i64),
-    Hash(String),
-}
+const SESSION_ID_HEADER: &str = "X-Transmission-Session-Id";
 
+/// Transmission answers with 409 and a new session id whenever the session changes.
+/// Concurrent requests may race the renegotiation, so a few attempts are allowed.
+const MAX_ATTEMPTS: usize = 3;
+
+/// Transmission RPC client.
+///
+/// Requests share a connection pool and a session id, and are never serialized,
+/// so independent calls can run concurrently.
 #[derive(Clone)]
 pub struct TransmissionClient {
-    client: Arc<Mutex<TransClient>>,
+    client: Client,
+    url: String,
+    credentials: Option<(String, String)>,
+    session_id: Arc<RwLock<Option<String>>>,
     download_dir: Option<String>,
     dry_run: bool,
 }
@@ -36,13 +49,28 @@ pub enum TransmissionClientError {
     #[error("Missing download dir")]
     MissingDownloadDir,
     #[error("Unable to perform RPC request on transmission server: {0}")]
-    TransmissionError(#[from] Box<dyn std::error::Error + Send + Sync>),
+    Request(#[from] reqwest::Error),
+    #[error("Unexpected status code: {0}")]
+    Status(StatusCode),
+    #[error("No session id received on session renegotiation")]
+    MissingSessionId,
+    #[error("Unable to negotiate session after {MAX_ATTEMPTS} attempts")]
+    SessionNegotiation,
+    #[error("Unable to parse RPC response arguments: {0}")]
+    Arguments(#[from] serde_json::Error),
     #[error("Missing torrent hash")]
     MissingHashString,
 }
 
 pub type TransmissionClientResult<T> = Result<T, TransmissionClientError>;
 
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
 impl TransmissionClient {
     pub fn create(
         url: String,
@@ -51,56 +79,144 @@ impl TransmissionClient {
         download_dir: Option<String>,
         dry_run: bool,
     ) -> Self {
-        let url = url
-            .parse()
-            .expect("Unable to parse transmission server url");
-
-        let client = match (username, password) {
-            (Some(user), Some(password)) => {
-                TransClient::with_auth(url, BasicAuth { user, password })
-            }
-            _ => TransClient::new(url),
-        };
+        let client = Client::builder()
+            .build()
+            .expect("Unable to create HTTP client");
 
         Self {
-            client: Arc::new(Mutex::new(client)),
+            client,
+            url,
+            credentials: username.zip(password),
+            session_id: Arc::new(RwLock::new(None)),
             download_dir,
             dry_run,
         }
     }
 
+    /// Performs RPC call, renegotiating session id if transmission asks for it.
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        arguments: Value,
+    ) -> TransmissionClientResult<T> {
+        let body = json!({ "method": method, "arguments": arguments });
+
+        for _ in 0..MAX_ATTEMPTS {
+            let mut request = self.client.post(&self.url).json(&body);
+
+            if let Some((username, password)) = &self.credentials {
+                request = request.basic_auth(username, Some(password));
+            }
+
+            // The lock guard is dropped right away, so it's never held across await.
+            let session_id = self.session_id.read().clone();
+            if let Some(session_id) = session_id {
+                request = request.header(SESSION_ID_HEADER, session_id);
+            }
+
+            let response = request.send().await?;
+
+            if response.status() == StatusCode::CONFLICT {
+                let session_id = response
+                    .headers()
+                    .get(SESSION_ID_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .ok_or(TransmissionClientError::MissingSessionId)?;
+
+                debug!(session_id, "Got new session id");
+
+                *self.session_id.write() = Some(session_id.to_string());
+
+                continue;
+            }
+
+            if response.status() != StatusCode::OK {
+                return Err(TransmissionClientError::Status(response.status()));
+            }
+
+            let RpcResponse { result, arguments } = response.json::<RpcResponse>().await?;
+
+            debug!(?result, "Result of {} call", method);
+
+            if result != "success" {
+                return Err(TransmissionClientError::ErroneousResult(result));
+            }
+
+            return Ok(serde_json::from_value(arguments)?);
+        }
+
+        Err(TransmissionClientError::SessionNegotiation)
+    }
+
+    async fn get_torrent(
+        &self,
+        torrent_id: &TorrentId,
+        fields: &[TorrentGetField],
+    ) -> TransmissionClientResult<Option<Torrent>> {
+        let Torrents { torrents } = self
+            .call::<Torrents<Torrent>>(
+                "torrent-get",
+                json!({
+                    "ids": [Id::from(torrent_id)],
+                    "fields": fields.iter().map(TorrentGetField::to_str).collect::<Vec<_>>(),
+                }),
+            )
+            .await?;
+
+        Ok(torrents.into_iter().next())
+    }
+
+    async fn set_torrent(
+        &self,
+        torrent_id: &TorrentId,
+        args: TorrentSetArgs,
+    ) -> TransmissionClientResult<()> {
+        let mut arguments = json!(args);
+        arguments["ids"] = json!([Id::from(torrent_id)]);
+
+        self.call::<Value>("torrent-set", arguments).await?;
+
+        Ok(())
+    }
+
+    fn download_dir(&self) -> TransmissionClientResult<&str> {
+        self.download_dir
+            .as_deref()
+            .ok_or(TransmissionClientError::MissingDownloadDir)
+    }
+
+    /// Adds torrent in paused state, so files can be selected and free space can be
+    /// checked before anything is downloaded. Use [`Self::start`] to start it.
     #[instrument(err, skip(self, torrent_file_content))]
     pub async fn add(
         &self,
         torrent_file_content: Vec<u8>,
         path: &str,
-    ) -> TransmissionClientResult<TorrentId> {
+    ) -> TransmissionClientResult<AddedTorrent> {
         let metainfo = general_purpose::STANDARD.encode(torrent_file_content);
-        let dry_run = self.dry_run;
-        let download_dir = self
-            .download_dir
-            .as_ref()
-            .ok_or_else(|| TransmissionClientError::MissingDownloadDir)?;
-
-        let RpcResponse {
-            arguments,
-            result: _,
-        } = self
-            .client
-            .lock()
-            .torrent_add(TorrentAddArgs {
-                metainfo: Some(metainfo.clone()),
-                download_dir: Some(format!("{}/{}/", download_dir, path)),
-                paused: Some(dry_run),
-                ..TorrentAddArgs::default()
-            })
+
+        let added = self
+            .call::<TorrentAddedOrDuplicate>(
+                "torrent-add",
+                json!(TorrentAddArgs {
+                    metainfo: Some(metainfo),
+                    download_dir: Some(format!("{}/{}/", self.download_dir()?, path)),
+                    paused: Some(true),
+                    ..TorrentAddArgs::default()
+                }),
+            )
             .await?;
 
-        match arguments {
-            TorrentAddedOrDuplicate::TorrentDuplicate(torrent) => torrent.try_into(),
-            TorrentAddedOrDuplicate::TorrentAdded(torrent) => torrent.try_into(),
-            TorrentAddedOrDuplicate::Error => Err(TransmissionClientError::Error),
-        }
+        let (torrent, is_duplicate) = match added {
+            TorrentAddedOrDuplicate::TorrentDuplicate(torrent) => (torrent, true),
+            TorrentAddedOrDuplicate::TorrentAdded(torrent) => (torrent, false),
+            TorrentAddedOrDuplicate::Error => return Err(TransmissionClientError::Error),
+        };
+
+        Ok(AddedTorrent {
+            id: torrent.try_into()?,
+            is_duplicate,
+        })
     }
 
     #[instrument(err, skip(self))]
@@ -109,41 +225,479 @@ impl TransmissionClient {
         torrent_id: &TorrentId,
         remove_strategy: RemoveStrategy,
     ) -> TransmissionClientResult<()> {
-        let RpcResponse {
-            result,
-            arguments: _,
-        } = self
-            .client
-            .lock()
-            .torrent_remove(
-                vec![torrent_id.into()],
-                match remove_strategy {
+        self.call::<Value>(
+            "torrent-remove",
+            json!({
+                "ids": [Id::from(torrent_id)],
+                "delete-local-data": match remove_strategy {
                     RemoveStrategy::KeepLocalData => false,
                     RemoveStrategy::DeleteLocalData => true,
                 },
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn get_status(
+        &self,
+        torrent_id: &TorrentId,
+    ) -> TransmissionClientResult<Option<TorrentStatus>> {
+        let torrent = self
+            .get_torrent(
+                torrent_id,
+                &[
+                    TorrentGetField::IsFinished,
+                    TorrentGetField::LeftUntilDone,
+                    TorrentGetField::DownloadDir,
+                    TorrentGetField::Labels,
+                ],
             )
             .await?;
 
-        debug!(?result, "Result of torrent_remove call");
+        Ok(torrent.map(|torrent| TorrentStatus {
+            is_finished: torrent.is_finished.unwrap_or_default(),
+            left_until_done: torrent.left_until_done.unwrap_or_default().max(0) as u64,
+            download_dir: torrent.download_dir,
+            labels: torrent.labels.unwrap_or_default(),
+        }))
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn set_location(
+        &self,
+        torrent_id: &TorrentId,
+        path: &str,
+    ) -> TransmissionClientResult<()> {
+        self.call::<Value>(
+            "torrent-set-location",
+            json!({
+                "ids": [Id::from(torrent_id)],
+                "location": format!("{}/{}/", self.download_dir()?, path),
+                "move": true,
+            }),
+        )
+        .await?;
 
         Ok(())
     }
 
     #[instrument(err, skip(self))]
-    pub async fn get_is_downloaded(
+    pub async fn set_labels(
+        &self,
+        torrent_id: &TorrentId,
+        labels: Vec<String>,
+    ) -> TransmissionClientResult<()> {
+        let mut args = TorrentSetArgs::default();
+        args.labels = Some(labels);
+
+        self.set_torrent(torrent_id, args).await
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn get_files(
+        &self,
+        torrent_id: &TorrentId,
+    ) -> TransmissionClientResult<Vec<TorrentFile>> {
+        let torrent = self
+            .get_torrent(torrent_id, &[TorrentGetField::Files])
+            .await?;
+
+        Ok(torrent
+            .and_then(|torrent| torrent.files)
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+            .map(|(index, file)| TorrentFile {
+                index,
+                name: file.name,
+                length: file.length.max(0) as u64,
+            })
+            .collect())
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn set_files_wanted(
+        &self,
+        torrent_id: &TorrentId,
+        files_wanted: Vec<usize>,
+        files_unwanted: Vec<usize>,
+    ) -> TransmissionClientResult<()> {
+        // Transmission treats an empty list as "all files", so empty lists are omitted.
+        let mut args = TorrentSetArgs::default();
+        args.files_wanted = Some(files_wanted).filter(|f| !f.is_empty());
+        args.files_unwanted = Some(files_unwanted).filter(|f| !f.is_empty());
+
+        self.set_torrent(torrent_id, args).await
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn rename_path(
         &self,
         torrent_id: &TorrentId,
-    ) -> TransmissionClientResult<bool> {
-        let RpcResponse { arguments, .. } = self
-            .client
-            .lock()
-            .torrent_get(None, Some(vec![torrent_id.into()]))
+        path: &str,
+        name: &str,
+    ) -> TransmissionClientResult<()> {
+        self.call::<Value>(
+            "torrent-rename-path",
+            json!({
+                "ids": [Id::from(torrent_id)],
+                "path": path,
+                "name": name,
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn verify(&self, torrent_id: &TorrentId) -> TransmissionClientResult<()> {
+        self.call::<Value>("torrent-verify", json!({ "ids": [Id::from(torrent_id)] }))
             .await?;
 
-        Ok(arguments
-            .torrents
-            .first()
-            .filter(|torrent| torrent.is_finished.unwrap_or_default())
-            .is_some())
+        Ok(())
+    }
+
+    /// Starts torrent. Does nothing in dry run mode, so torrents stay paused.
+    #[instrument(err, skip(self))]
+    pub async fn start(&self, torrent_id: &TorrentId) -> TransmissionClientResult<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+
+        self.call::<Value>("torrent-start", json!({ "ids": [Id::from(torrent_id)] }))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns free space in the download directory. Category directories live inside it,
+    /// and may not exist yet, so the download directory itself is queried.
+    #[instrument(err, skip(self))]
+    pub async fn get_free_space(&self) -> TransmissionClientResult<u64> {
+        let free_space = self
+            .call::<FreeSpace>("free-space", json!({ "path": self.download_dir()? }))
+            .await?;
+
+        Ok(free_space.size_bytes.max(0) as u64)
+    }
+
+    #[instrument(err, skip(self))]
+    pub async fn get_session_settings(&self) -> TransmissionClientResult<SessionSettings> {
+        self.call::<SessionSettings>("session-get", json!({ "fields": SessionSettings::FIELDS }))
+            .await
+    }
+
+    /// Changes session settings. Only the fields that are set are sent to transmission.
+    #[instrument(err, skip(self))]
+    pub async fn set_session_settings(
+        &self,
+        settings: &SessionSettings,
+    ) -> TransmissionClientResult<()> {
+        self.call::<Value>("session-set", json!(settings)).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl DownloadClient for TransmissionClient {
+    async fn add(
+        &self,
+        torrent_file_content: Vec<u8>,
+        path: &str,
+    ) -> DownloadClientResult<AddedTorrent> {
+        Ok(TransmissionClient::add(self, torrent_file_content, path).await?)
+    }
+
+    async fn start(&self, torrent_id: &TorrentId) -> DownloadClientResult<()> {
+        Ok(TransmissionClient::start(self, torrent_id).await?)
+    }
+
+    async fn remove(
+        &self,
+        torrent_id: &TorrentId,
+        remove_strategy: RemoveStrategy,
+    ) -> DownloadClientResult<()> {
+        Ok(TransmissionClient::remove(self, torrent_id, remove_strategy).await?)
+    }
+
+    async fn get_status(
+        &self,
+        torrent_id: &TorrentId,
+    ) -> DownloadClientResult<Option<TorrentStatus>> {
+        Ok(TransmissionClient::get_status(self, torrent_id).await?)
+    }
+
+    async fn set_location(&self, torrent_id: &TorrentId, path: &str) -> DownloadClientResult<()> {
+        Ok(TransmissionClient::set_location(self, torrent_id, path).await?)
+    }
+
+    async fn set_labels(
+        &self,
+        torrent_id: &TorrentId,
+        labels: Vec<String>,
+    ) -> DownloadClientResult<()> {
+        Ok(TransmissionClient::set_labels(self, torrent_id, labels).await?)
+    }
+
+    async fn get_files(&self, torrent_id: &TorrentId) -> DownloadClientResult<Vec<TorrentFile>> {
+        Ok(TransmissionClient::get_files(self, torrent_id).await?)
+    }
+
+    async fn rename_path(
+        &self,
+        torrent_id: &TorrentId,
+        path: &str,
+        name: &str,
+    ) -> DownloadClientResult<()> {
+        Ok(TransmissionClient::rename_path(self, torrent_id, path, name).await?)
+    }
+
+    async fn verify(&self, torrent_id: &TorrentId) -> DownloadClientResult<()> {
+        Ok(TransmissionClient::verify(self, torrent_id).await?)
+    }
+
+    async fn set_files_wanted(
+        &self,
+        torrent_id: &TorrentId,
+        files_wanted: Vec<usize>,
+        files_unwanted: Vec<usize>,
+    ) -> DownloadClientResult<()> {
+        Ok(
+            TransmissionClient::set_files_wanted(self, torrent_id, files_wanted, files_unwanted)
+                .await?,
+        )
+    }
+
+    async fn get_free_space(&self) -> DownloadClientResult<u64> {
+        Ok(TransmissionClient::get_free_space(self).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use wiremock::matchers::{body_partial_json, header, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    fn create_client(server: &MockServer) -> TransmissionClient {
+        TransmissionClient::create(
+            format!("{}/transmission/rpc", server.uri()),
+            None,
+            None,
+            Some("/data".to_string()),
+            false,
+        )
+    }
+
+    fn torrent_get_response() -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "result": "success",
+            "arguments": {
+                "torrents": [{
+                    "isFinished": true,
+                    "leftUntilDone": 0,
+                    "downloadDir": "/data/Series/",
+                    "labels": ["series"]
+                }]
+            }
+        }))
+    }
+
+    fn torrent_id() -> TorrentId {
+        TorrentId::Hash("abc".to_string())
+    }
+
+    #[actix_rt::test]
+    async fn test_renegotiates_session_id() {
+        let server = MockServer::start().await;
+        let client = create_client(&server);
+
+        Mock::given(method("POST"))
+            .and(header(SESSION_ID_HEADER, "session-1"))
+            .respond_with(torrent_get_response())
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(409).insert_header(SESSION_ID_HEADER, "session-1"))
+            .mount(&server)
+            .await;
+
+        let status = client.get_status(&torrent_id()).await.unwrap().unwrap();
+
+        assert!(status.is_finished);
+        assert_eq!(status.download_dir.as_deref(), Some("/data/Series/"));
+        assert_eq!(status.labels, vec!["series"]);
+
+        // Session id is reused by the following requests.
+        client.get_status(&torrent_id()).await.unwrap();
+
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn test_fails_when_session_id_is_missing() {
+        let server = MockServer::start().await;
+        let client = create_client(&server);
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(409))
+            .mount(&server)
+            .await;
+
+        let result = client.get_status(&torrent_id()).await;
+
+        assert!(matches!(
+            result,
+            Err(TransmissionClientError::MissingSessionId)
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_erroneous_result() {
+        let server = MockServer::start().await;
+        let client = create_client(&server);
+
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "free-space" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": "No such file or directory",
+                "arguments": {}
+            })))
+            .mount(&server)
+            .await;
+
+        let result = client.get_free_space().await;
+
+        assert!(matches!(
+            result,
+            Err(TransmissionClientError::ErroneousResult(result)) if result == "No such file or directory"
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_parallel_status_polls_do_not_block_each_other() {
+        const POLLS: usize = 5;
+        const DELAY: Duration = Duration::from_millis(500);
+
+        let server = MockServer::start().await;
+        let client = create_client(&server);
+
+        Mock::given(method("POST"))
+            .respond_with(torrent_get_response().set_delay(DELAY))
+            .expect(POLLS as u64)
+            .mount(&server)
+            .await;
+
+        let started_at = Instant::now();
+        let handles = (0..POLLS)
+            .map(|_| {
+                let client = client.clone();
+                actix_rt::spawn(async move { client.get_status(&torrent_id()).await })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            assert!(handle.await.unwrap().unwrap().is_some());
+        }
+
+        // Serialized requests would take at least POLLS * DELAY.
+        assert!(started_at.elapsed() < DELAY * 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_session_settings() {
+        let server = MockServer::start().await;
+        let client = create_client(&server);
+
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "session-get" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": "success",
+                "arguments": {
+                    "speed-limit-down": 1000,
+                    "speed-limit-down-enabled": true,
+                    "alt-speed-enabled": false,
+                    "alt-speed-time-begin": 540,
+                    "alt-speed-time-day": 62,
+                    "peer-limit-global": 200,
+                    "download-queue-size": 5,
+                    "version": "4.0.5"
+                }
+            })))
+            .mount(&server)
+            .await;
+
+        let settings = client.get_session_settings().await.unwrap();
+
+        assert_eq!(
+            settings,
+            SessionSettings {
+                speed_limit_down: Some(1000),
+                speed_limit_down_enabled: Some(true),
+                alt_speed_enabled: Some(false),
+                alt_speed_time_begin: Some(540),
+                alt_speed_time_day: Some(62),
+                peer_limit_global: Some(200),
+                download_queue_size: Some(5),
+                ..SessionSettings::default()
+            }
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_set_session_settings_sends_only_set_fields() {
+        let server = MockServer::start().await;
+        let client = create_client(&server);
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": "success",
+                "arguments": {}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client
+            .set_session_settings(&SessionSettings {
+                alt_speed_enabled: Some(true),
+                alt_speed_down: Some(500),
+                ..SessionSettings::default()
+            })
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let body = requests[0].body_json::<Value>().unwrap();
+
+        assert_eq!(
+            body,
+            json!({
+                "method": "session-set",
+                "arguments": {
+                    "alt-speed-enabled": true,
+                    "alt-speed-down": 500
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_futures_are_send() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let client = TransmissionClient::create(String::new(), None, None, None, false);
+        let torrent_id = torrent_id();
+
+        assert_send(&client.get_status(&torrent_id));
+        assert_send(&DownloadClient::get_status(&client, &torrent_id));
     }
 }
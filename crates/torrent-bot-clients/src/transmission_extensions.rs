@@ -1,4 +1,5 @@
-use crate::transmission::{TorrentId, TransmissionClientError};
+use crate::download_client::TorrentId;
+use crate::transmission::TransmissionClientError;
 use transmission_rpc::types::{Id, Torrent};
 
 impl From<&TorrentId> for Id {
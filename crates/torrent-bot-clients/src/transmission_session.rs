@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+
+/// Days of the week for [`SessionSettings::alt_speed_time_day`], combined as a bitmask.
+pub mod alt_speed_days {
+    pub const SUNDAY: u8 = 1;
+    pub const MONDAY: u8 = 2;
+    pub const TUESDAY: u8 = 4;
+    pub const WEDNESDAY: u8 = 8;
+    pub const THURSDAY: u8 = 16;
+    pub const FRIDAY: u8 = 32;
+    pub const SATURDAY: u8 = 64;
+    pub const WEEKDAYS: u8 = MONDAY | TUESDAY | WEDNESDAY | THURSDAY | FRIDAY;
+    pub const WEEKEND: u8 = SUNDAY | SATURDAY;
+    pub const ALL: u8 = WEEKDAYS | WEEKEND;
+}
+
+/// Transmission session settings the bot manages.
+///
+/// Speeds are in KB/s, schedule times are minutes after midnight.
+/// Fields left as `None` are not changed by `session-set`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SessionSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_limit_down: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_limit_down_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_limit_up: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_limit_up_enabled: Option<bool>,
+
+    /// Turtle mode: alternative speed limits used instead of the global ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt_speed_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt_speed_down: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt_speed_up: Option<u32>,
+    /// Turns turtle mode on and off by the schedule below.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt_speed_time_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt_speed_time_begin: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt_speed_time_end: Option<u16>,
+    /// See [`alt_speed_days`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt_speed_time_day: Option<u8>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_limit_global: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_limit_per_torrent: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_queue_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_queue_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed_queue_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed_queue_size: Option<u32>,
+}
+
+impl SessionSettings {
+    /// Names of the fields requested from `session-get`.
+    pub(crate) const FIELDS: [&'static str; 17] = [
+        "speed-limit-down",
+        "speed-limit-down-enabled",
+        "speed-limit-up",
+        "speed-limit-up-enabled",
+        "alt-speed-enabled",
+        "alt-speed-down",
+        "alt-speed-up",
+        "alt-speed-time-enabled",
+        "alt-speed-time-begin",
+        "alt-speed-time-end",
+        "alt-speed-time-day",
+        "peer-limit-global",
+        "peer-limit-per-torrent",
+        "download-queue-enabled",
+        "download-queue-size",
+        "seed-queue-enabled",
+        "seed-queue-size",
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields_match_serialized_names() {
+        let settings = SessionSettings {
+            speed_limit_down: Some(0),
+            speed_limit_down_enabled: Some(false),
+            speed_limit_up: Some(0),
+            speed_limit_up_enabled: Some(false),
+            alt_speed_enabled: Some(false),
+            alt_speed_down: Some(0),
+            alt_speed_up: Some(0),
+            alt_speed_time_enabled: Some(false),
+            alt_speed_time_begin: Some(0),
+            alt_speed_time_end: Some(0),
+            alt_speed_time_day: Some(alt_speed_days::ALL),
+            peer_limit_global: Some(0),
+            peer_limit_per_torrent: Some(0),
+            download_queue_enabled: Some(false),
+            download_queue_size: Some(0),
+            seed_queue_enabled: Some(false),
+            seed_queue_size: Some(0),
+        };
+
+        let value = serde_json::to_value(settings).unwrap();
+        let mut names = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut fields = SessionSettings::FIELDS.map(String::from).to_vec();
+        names.sort();
+        fields.sort();
+
+        assert_eq!(names, fields);
+    }
+}
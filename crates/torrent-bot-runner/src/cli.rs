@@ -0,0 +1,685 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+
+use torrent_bot_clients::download_client::{DownloadClient, DownloadClientError};
+use torrent_bot_clients::toloka::TolokaClient;
+
+use crate::client::Client;
+use crate::config::{Config, StorageBackend};
+use crate::event_log::{parse_time, Event, EventFilter, EventKind};
+use crate::report::SyncReport;
+use crate::task_db::{open_task_db, Task, TaskDb, TaskOverrides, TaskStatus, MAX_REPORTS};
+
+/// Keeps topics watched on toloka in sync with the download client.
+/// Settings are read from environment variables, see README.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub(crate) struct Cli {
+    /// Runs a sync if omitted.
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Runs a sync, or keeps syncing in daemon mode.
+    Sync(SyncArgs),
+    /// Inspects and changes tasks in the storage.
+    #[command(subcommand)]
+    Tasks(TasksCommand),
+    /// Shows what the bot did, oldest first. Events are kept after their tasks are deleted.
+    Events(EventsArgs),
+    /// Backs up or restores the storage.
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// Validates config and connectivity to toloka, the download client and the server.
+    Check,
+}
+
+#[derive(Debug, Default, Args)]
+pub(crate) struct SyncArgs {
+    /// Applies deletions blocked by the mass deletion guard. Only the first sync
+    /// is allowed to, later ones in daemon mode are guarded again.
+    #[arg(long)]
+    pub(crate) allow_mass_deletion: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum TasksCommand {
+    /// Lists tasks with their status.
+    List {
+        /// Prints tasks as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Drops the task, without touching the download client.
+    Forget { topic_id: String },
+    /// Retries the task on the next sync, even if it failed too many times.
+    Retry { topic_id: String },
+    /// Cancels the deletion of a topic pending deletion. The task is pinned, so it isn't
+    /// deleted again while the topic isn't watched. Unpin it with `tasks set --pinned false`.
+    Restore { topic_id: String },
+    /// Changes how the topic is synced. Creates a task if the topic has none yet,
+    /// so overrides can be set before the topic is watched.
+    Set {
+        topic_id: String,
+        /// Never delete the task when the topic isn't watched anymore.
+        #[arg(long)]
+        pinned: Option<bool>,
+        /// Download the topic once and ignore its new versions.
+        #[arg(long)]
+        skip_updates: Option<bool>,
+        /// Track the topic without downloading it.
+        #[arg(long)]
+        track_only: Option<bool>,
+        /// Download path used instead of the path template, relative to the download directory.
+        #[arg(long, conflicts_with = "default_directory", value_parser = parse_directory)]
+        directory: Option<String>,
+        /// Use the path template again.
+        #[arg(long)]
+        default_directory: bool,
+    },
+}
+
+/// Parses a download path, which must stay inside the download directory.
+fn parse_directory(value: &str) -> Result<String, String> {
+    let path = Path::new(value);
+    let is_inside = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+    match path.components().next().is_some() && is_inside {
+        true => Ok(value.to_string()),
+        false => Err(format!(
+            "Expected a path relative to the download directory, without `..`, got {}",
+            value
+        )),
+    }
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct EventsArgs {
+    /// Only events at or after this time: RFC 3339, or how long ago, like `12h` or `7d`.
+    #[arg(long, value_parser = parse_time)]
+    since: Option<DateTime<Utc>>,
+    /// Only events at or before this time, in the same format as `--since`.
+    #[arg(long, value_parser = parse_time)]
+    until: Option<DateTime<Utc>>,
+    /// Only events of the topic.
+    #[arg(long)]
+    topic: Option<String>,
+    /// Only events of the kind.
+    #[arg(long)]
+    kind: Option<EventKind>,
+    /// Prints events as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum DbCommand {
+    /// Writes tasks, sync reports and events as JSON.
+    Export {
+        /// Writes to stdout if omitted.
+        path: Option<PathBuf>,
+    },
+    /// Replaces tasks with the ones from an export, and adds its sync reports and events.
+    /// Events that are already stored are skipped, so an export can be imported again.
+    Import { path: PathBuf },
+    /// Copies tasks, sync reports and events to another storage, e.g. to switch backends.
+    /// The target storage must be empty.
+    Copy {
+        /// Backend of the target storage.
+        #[arg(long)]
+        to: StorageBackend,
+        /// Path of the target storage.
+        path: String,
+    },
+    /// Removes tasks that can't be read, e.g. ones written by a newer runner.
+    /// Topics that are still watched are added again on the next sync.
+    Repair,
+}
+
+/// Error of a command, printed before the runner exits with failure.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum CommandError {
+    #[error("{0}")]
+    Storage(#[from] crate::task_db::StorageError),
+    #[error("Unable to read or write file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid export: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("No task for topic {0}")]
+    TaskNotFound(String),
+    #[error("Topic {0} isn't pending deletion")]
+    NotPendingDeletion(String),
+    #[error("Some checks failed")]
+    CheckFailed,
+    #[error("Target storage isn't empty")]
+    TargetNotEmpty,
+    #[error("Missing {0} config")]
+    MissingConfig(&'static str),
+    #[error("{0}")]
+    DownloadClient(#[from] DownloadClientError),
+}
+
+pub(crate) type CommandResult = Result<(), CommandError>;
+
+/// Contents of the storage written by `db export`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Export {
+    tasks: Vec<Task>,
+    #[serde(default)]
+    reports: Vec<SyncReport>,
+    #[serde(default)]
+    events: Vec<Event>,
+}
+
+pub(crate) fn run_tasks_command(task_db: &dyn TaskDb, command: TasksCommand) -> CommandResult {
+    match command {
+        TasksCommand::List { json } => {
+            let tasks = task_db.get_tasks()?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&tasks)?);
+                return Ok(());
+            }
+
+            for task in tasks.iter() {
+                println!(
+                    "{:<18} {:<10} {}",
+                    format!("{:?}", task.task_status),
+                    task.topic_id,
+                    task.topic_title
+                );
+
+                if task.overrides != TaskOverrides::default() {
+                    println!("{:<18} {:<10} {}", "", "", describe(&task.overrides));
+                }
+
+                if let Some(error) = &task.last_error {
+                    println!(
+                        "{:<18} {:<10} {} attempts: {}",
+                        "", "", task.attempts, error
+                    );
+                }
+            }
+        }
+        TasksCommand::Forget { topic_id } => {
+            if task_db.get_task_by_topic_id(&topic_id)?.is_none() {
+                return Err(CommandError::TaskNotFound(topic_id));
+            }
+
+            task_db.delete_task_by_topic_id(&topic_id)?;
+
+            println!("Forgot task for topic {}", topic_id);
+        }
+        TasksCommand::Retry { topic_id } => {
+            let Some(mut task) = task_db.get_task_by_topic_id(&topic_id)? else {
+                return Err(CommandError::TaskNotFound(topic_id));
+            };
+
+            if matches!(task.task_status, TaskStatus::PermanentlyFailed) {
+                task.task_status = TaskStatus::Added;
+            }
+            task.attempts = 0;
+            task.next_retry_at = None;
+            task.last_error = None;
+
+            task_db.replace_task(task)?;
+
+            println!("Task for topic {} is retried on the next sync", topic_id);
+        }
+        TasksCommand::Restore { topic_id } => {
+            let Some(task) = task_db.get_task_by_topic_id(&topic_id)? else {
+                return Err(CommandError::TaskNotFound(topic_id));
+            };
+            if !matches!(task.task_status, TaskStatus::PendingDeletion) {
+                return Err(CommandError::NotPendingDeletion(topic_id));
+            }
+
+            // A downloaded torrent is marked as finished again on the next sync.
+            task_db.restore_task_by_topic_id(&topic_id, TaskStatus::Added)?;
+            task_db.update_task(&topic_id, &|task| task.overrides.pinned = true)?;
+
+            println!("Task for topic {} is restored and pinned", topic_id);
+        }
+        TasksCommand::Set {
+            topic_id,
+            pinned,
+            skip_updates,
+            track_only,
+            directory,
+            default_directory,
+        } => {
+            let mut task = task_db
+                .get_task_by_topic_id(&topic_id)?
+                .unwrap_or_else(|| Task {
+                    topic_id: topic_id.clone(),
+                    topic_title: topic_id.clone(),
+                    previous_title: None,
+                    topic_download_registered_at: String::new(),
+                    transmission_torrent_id: None,
+                    torrent_hash: None,
+                    category: None,
+                    size: None,
+                    poster_url: None,
+                    task_status: TaskStatus::Added,
+                    last_error: None,
+                    attempts: 0,
+                    next_retry_at: None,
+                    pending_deletion_since: None,
+                    overrides: TaskOverrides::default(),
+                });
+
+            let overrides = &mut task.overrides;
+            overrides.pinned = pinned.unwrap_or(overrides.pinned);
+            overrides.skip_updates = skip_updates.unwrap_or(overrides.skip_updates);
+            overrides.track_only = track_only.unwrap_or(overrides.track_only);
+            if directory.is_some() || default_directory {
+                overrides.directory = directory;
+            }
+
+            println!("Topic {}: {}", topic_id, describe(overrides));
+
+            task_db.replace_task(task)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn describe(overrides: &TaskOverrides) -> String {
+    let mut parts = vec![];
+
+    if overrides.pinned {
+        parts.push("pinned".to_string());
+    }
+    if overrides.skip_updates {
+        parts.push("skips updates".to_string());
+    }
+    if overrides.track_only {
+        parts.push("tracked only".to_string());
+    }
+    if let Some(directory) = &overrides.directory {
+        parts.push(format!("directory {}", directory));
+    }
+
+    match parts.is_empty() {
+        true => "no overrides".to_string(),
+        false => parts.join(", "),
+    }
+}
+
+pub(crate) fn run_events_command(task_db: &dyn TaskDb, args: EventsArgs) -> CommandResult {
+    let events = task_db.get_events(&EventFilter {
+        since: args.since,
+        until: args.until,
+        topic_id: args.topic,
+        kind: args.kind,
+    })?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&events)?);
+        return Ok(());
+    }
+
+    for event in events.iter() {
+        println!(
+            "{} {:<8} {:<10} {}",
+            event.at.format("%Y-%m-%d %H:%M:%S"),
+            event.kind,
+            event.topic_id,
+            event.title
+        );
+
+        if let Some(error) = &event.error {
+            println!("{:<19} {:<8} {:<10} {}", "", "", "", error);
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn run_db_command(task_db: &dyn TaskDb, command: DbCommand) -> CommandResult {
+    match command {
+        DbCommand::Export { path } => {
+            let export = Export {
+                tasks: task_db.get_tasks()?,
+                reports: task_db.get_reports(MAX_REPORTS)?,
+                events: task_db.get_events(&EventFilter::default())?,
+            };
+
+            match path {
+                Some(path) => {
+                    let writer = BufWriter::new(File::create(&path)?);
+                    serde_json::to_writer_pretty(writer, &export)?;
+
+                    eprintln!(
+                        "Exported {} tasks and {} events to {}",
+                        export.tasks.len(),
+                        export.events.len(),
+                        path.display()
+                    );
+                }
+                None => println!("{}", serde_json::to_string_pretty(&export)?),
+            }
+        }
+        DbCommand::Import { path } => {
+            let reader = BufReader::new(File::open(&path)?);
+            let export: Export = serde_json::from_reader(reader)?;
+
+            import(task_db, &export)?;
+
+            println!(
+                "Imported {} tasks and {} events from {}",
+                export.tasks.len(),
+                export.events.len(),
+                path.display()
+            );
+        }
+        DbCommand::Copy { to, path } => {
+            let target = open_task_db(&to, &path)?;
+            copy_storage(task_db, target.as_ref())?;
+        }
+        DbCommand::Repair => {
+            let topic_ids = task_db.get_invalid_topic_ids()?;
+
+            for topic_id in topic_ids.iter() {
+                task_db.delete_task_by_topic_id(topic_id)?;
+                println!("Removed unreadable task for topic {}", topic_id);
+            }
+
+            println!("Removed {} unreadable tasks", topic_ids.len());
+        }
+    }
+
+    Ok(())
+}
+
+fn import(task_db: &dyn TaskDb, export: &Export) -> CommandResult {
+    task_db.save_tasks(&export.tasks)?;
+    for report in export.reports.iter() {
+        task_db.save_report(report)?;
+    }
+
+    let stored = task_db.get_events(&EventFilter::default())?;
+    for event in export.events.iter().filter(|event| !stored.contains(event)) {
+        task_db.append_event(event)?;
+    }
+
+    Ok(())
+}
+
+/// Copies everything to an empty storage, so nothing is merged or lost.
+fn copy_storage(from: &dyn TaskDb, to: &dyn TaskDb) -> CommandResult {
+    let all_events = EventFilter::default();
+
+    if !to.get_tasks()?.is_empty() || !to.get_events(&all_events)?.is_empty() {
+        return Err(CommandError::TargetNotEmpty);
+    }
+
+    let tasks = from.get_tasks()?;
+    let reports = from.get_reports(usize::MAX)?;
+    let events = from.get_events(&all_events)?;
+
+    to.save_tasks(&tasks)?;
+    for report in reports.iter() {
+        to.save_report(report)?;
+    }
+    for event in events.iter() {
+        to.append_event(event)?;
+    }
+    to.flush()?;
+
+    println!(
+        "Copied {} tasks, {} sync reports and {} events",
+        tasks.len(),
+        reports.len(),
+        events.len()
+    );
+
+    Ok(())
+}
+
+/// Checks every dependency of the sync, reporting each one,
+/// so all problems are visible at once.
+pub(crate) async fn run_check(
+    config: &Config,
+    task_db: &dyn TaskDb,
+    download_client: Result<Box<dyn DownloadClient>, CommandError>,
+) -> CommandResult {
+    let mut ok = true;
+    let mut report = |name: &str, result: Result<String, String>| match result {
+        Ok(details) => println!("[ok]     {}: {}", name, details),
+        Err(error) => {
+            ok = false;
+            println!("[failed] {}: {}", name, error);
+        }
+    };
+
+    report(
+        "storage",
+        task_db
+            .get_tasks()
+            .map(|tasks| format!("{} tasks", tasks.len()))
+            .map_err(|error| error.to_string()),
+    );
+
+    let toloka = match TolokaClient::create(&config.toloka.username, &config.toloka.password).await
+    {
+        Ok(toloka) => toloka.get_watched_topics().await.map(|watched| {
+            format!(
+                "{} watched topics, {} unknown",
+                watched.topics.len(),
+                watched.unknown_topic_ids.len()
+            )
+        }),
+        Err(error) => Err(error),
+    };
+    report("toloka", toloka.map_err(|error| error.to_string()));
+
+    let download_client = match download_client {
+        Ok(download_client) => download_client
+            .get_free_space()
+            .await
+            .map(|free_space| format!("{} MiB free", free_space / 1024 / 1024))
+            .map_err(|error| error.to_string()),
+        Err(error) => Err(error.to_string()),
+    };
+    report("download client", download_client);
+
+    let server = Client::create(&config.server_endpoint)
+        .check_health()
+        .await
+        .map(|_| config.server_endpoint.clone())
+        .map_err(|error| error.to_string());
+    report("server", server);
+
+    match ok {
+        true => Ok(()),
+        false => Err(CommandError::CheckFailed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_copies_storage_between_backends() {
+        use crate::event_log::{Event, EventKind};
+        use crate::sled_task_db::SledTaskDb;
+        use crate::sqlite_task_db::SqliteTaskDb;
+        use crate::task_db::test_suite::{task, topic_ids};
+
+        let sled = sled::Config::new().temporary(true).open().unwrap();
+        let from = SledTaskDb::open(sled).unwrap();
+        let to = SqliteTaskDb::create(":memory:").unwrap();
+
+        from.save_tasks(&[task("1"), task("2")]).unwrap();
+        from.save_report(&SyncReport::start()).unwrap();
+        from.append_event(&Event::new(EventKind::Added, "1", "Topic 1"))
+            .unwrap();
+
+        copy_storage(&from, &to).unwrap();
+
+        assert_eq!(topic_ids(&to), vec!["1", "2"]);
+        assert_eq!(to.get_reports(10).unwrap().len(), 1);
+        assert_eq!(to.get_events(&EventFilter::default()).unwrap().len(), 1);
+        assert!(matches!(
+            copy_storage(&from, &to),
+            Err(CommandError::TargetNotEmpty)
+        ));
+    }
+
+    #[test]
+    fn test_imports_events_once() {
+        use crate::sqlite_task_db::SqliteTaskDb;
+        use crate::task_db::test_suite::task;
+
+        let task_db = SqliteTaskDb::create(":memory:").unwrap();
+        let export = Export {
+            tasks: vec![task("1")],
+            reports: vec![],
+            events: vec![
+                Event::new(EventKind::Added, "1", "Topic 1"),
+                Event::new(EventKind::Deleted, "2", "Topic 2"),
+            ],
+        };
+
+        import(&task_db, &export).unwrap();
+        import(&task_db, &export).unwrap();
+
+        assert_eq!(
+            task_db.get_events(&EventFilter::default()).unwrap().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_restores_task_pending_deletion() {
+        use crate::sqlite_task_db::SqliteTaskDb;
+        use crate::task_db::test_suite::task;
+
+        let task_db = SqliteTaskDb::create(":memory:").unwrap();
+        task_db.replace_task(task("1")).unwrap();
+        task_db
+            .mark_task_as_pending_deletion_by_topic_id("1", Utc::now())
+            .unwrap();
+
+        let restore = || TasksCommand::Restore {
+            topic_id: "1".to_string(),
+        };
+        run_tasks_command(&task_db, restore()).unwrap();
+
+        let task = task_db.get_task_by_topic_id("1").unwrap().unwrap();
+        assert!(matches!(task.task_status, TaskStatus::Added));
+        assert_eq!(task.pending_deletion_since, None);
+        assert!(task.overrides.pinned);
+        assert!(matches!(
+            run_tasks_command(&task_db, restore()),
+            Err(CommandError::NotPendingDeletion(_))
+        ));
+    }
+
+    #[test]
+    fn test_repair_removes_unreadable_tasks() {
+        use crate::sled_task_db::{SledTaskDb, TASKS_TREE};
+        use crate::task_db::test_suite::{task, topic_ids};
+
+        let sled = sled::Config::new().temporary(true).open().unwrap();
+        sled.open_tree(TASKS_TREE)
+            .unwrap()
+            .insert("2", r#"{"topic_id": 2}"#)
+            .unwrap();
+        let task_db = SledTaskDb::open(sled).unwrap();
+        task_db.replace_task(task("1")).unwrap();
+
+        run_db_command(&task_db, DbCommand::Repair).unwrap();
+
+        assert_eq!(topic_ids(&task_db), vec!["1"]);
+    }
+
+    #[test]
+    fn test_cli_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parses_commands() {
+        let cli = Cli::parse_from(["torrent-bot-runner"]);
+        assert!(cli.command.is_none());
+
+        let cli = Cli::parse_from(["torrent-bot-runner", "tasks", "retry", "t679577"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Tasks(TasksCommand::Retry { topic_id })) if topic_id == "t679577"
+        ));
+
+        let cli = Cli::parse_from([
+            "torrent-bot-runner",
+            "tasks",
+            "set",
+            "t679577",
+            "--skip-updates",
+            "true",
+            "--directory",
+            "Cartoons",
+        ]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Tasks(TasksCommand::Set {
+                skip_updates: Some(true),
+                pinned: None,
+                directory: Some(directory),
+                default_directory: false,
+                ..
+            })) if directory == "Cartoons"
+        ));
+
+        let cli = Cli::parse_from([
+            "torrent-bot-runner",
+            "events",
+            "--since",
+            "2024-06-01T22:00:00Z",
+            "--kind",
+            "deleted",
+        ]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Events(EventsArgs {
+                since: Some(_),
+                until: None,
+                kind: Some(EventKind::Deleted),
+                ..
+            }))
+        ));
+        assert!(Cli::try_parse_from(["torrent-bot-runner", "events", "--since", "soon"]).is_err());
+
+        for directory in ["/data", "../Cartoons", "Cartoons/../..", ""] {
+            assert!(Cli::try_parse_from([
+                "torrent-bot-runner",
+                "tasks",
+                "set",
+                "t1",
+                "--directory",
+                directory
+            ])
+            .is_err());
+        }
+
+        let cli = Cli::parse_from(["torrent-bot-runner", "db", "export"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Db(DbCommand::Export { path: None }))
+        ));
+    }
+}
@@ -1,6 +1,9 @@
-use serde_json::json;
 use tracing::error;
 
+use crate::event_log::Event;
+use crate::notification::Notification;
+use crate::report::SyncReport;
+
 pub(crate) struct Client {
     client: reqwest::Client,
     endpoint: String,
@@ -24,78 +27,91 @@ impl Client {
         }
     }
 
-    pub async fn send_topic_added(&self, title: &str) {
-        let text = format!("Added: {}", title);
+    /// Checks that the server is up and ready.
+    pub(crate) async fn check_health(&self) -> Result<(), ClientError> {
+        self.client
+            .get(format!("{}/health/ready", self.endpoint))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
 
+    /// Sends the notification about a topic, which the server renders and routes.
+    pub async fn send_notification(&self, notification: &Notification) {
         if let Err(error) = self
             .client
-            .post(format!(
-                "{}/internal/telegram-bot/send-message",
-                self.endpoint
-            ))
-            .json(&json!({
-                "text": text
-            }))
+            .post(format!("{}/internal/notifications", self.endpoint))
+            .json(notification)
             .send()
             .await
         {
-            error!(?error, "Failed to send 'Added' message");
+            error!(?error, kind = ?notification.kind, "Failed to send notification");
         }
     }
-    pub async fn send_topic_deleted(&self, title: &str) {
-        let text = format!("Deleted: {}", title);
 
+    /// Sends notifications of a sync run, which the server groups into one message.
+    pub async fn send_notification_digest(&self, notifications: &[Notification]) {
         if let Err(error) = self
             .client
-            .post(format!(
-                "{}/internal/telegram-bot/send-message",
-                self.endpoint
-            ))
-            .json(&json!({
-                "text": text
-            }))
+            .post(format!("{}/internal/notifications/digest", self.endpoint))
+            .json(notifications)
             .send()
             .await
         {
-            error!(?error, "Failed to send 'Deleted' message");
+            error!(
+                ?error,
+                count = notifications.len(),
+                "Failed to send notification digest"
+            );
         }
     }
 
-    pub async fn send_topic_updated(&self, title: &str) {
-        let text = format!("Updated: {}", title);
-
+    /// Sends topics whose deletion was blocked by the mass deletion guard,
+    /// which the server reports together.
+    pub async fn send_deletion_blocked(&self, notifications: &[Notification]) {
         if let Err(error) = self
             .client
             .post(format!(
-                "{}/internal/telegram-bot/send-message",
+                "{}/internal/notifications/deletion-blocked",
                 self.endpoint
             ))
-            .json(&json!({
-                "text": text
-            }))
+            .json(notifications)
             .send()
             .await
         {
-            error!(?error, "Failed to send 'Updated' message");
+            error!(
+                ?error,
+                count = notifications.len(),
+                "Failed to send blocked deletions"
+            );
         }
     }
 
-    pub async fn send_torrent_downloaded(&self, title: &str) {
-        let text = format!("Downloaded: {}", title);
+    /// Sends the report of a sync run, so recent runs can be inspected on the server.
+    pub async fn send_sync_report(&self, report: &SyncReport) {
+        if let Err(error) = self
+            .client
+            .post(format!("{}/internal/sync-reports", self.endpoint))
+            .json(report)
+            .send()
+            .await
+        {
+            error!(?error, "Failed to send sync report");
+        }
+    }
 
+    /// Sends events of a sync run to the server's event log.
+    pub async fn send_events(&self, events: &[Event]) {
         if let Err(error) = self
             .client
-            .post(format!(
-                "{}/internal/telegram-bot/send-message",
-                self.endpoint
-            ))
-            .json(&json!({
-                "text": text
-            }))
+            .post(format!("{}/internal/events", self.endpoint))
+            .json(events)
             .send()
             .await
         {
-            error!(?error, "Failed to send 'Downloaded' message");
+            error!(?error, "Failed to send events");
         }
     }
 }
@@ -1,5 +1,42 @@
 use serde::{de, Deserialize};
 
+use torrent_bot_clients::transmission_session::{alt_speed_days, SessionSettings};
+
+use crate::file_rules::FileSelectionConfig;
+use crate::path_template::PathTemplates;
+
+fn default_free_space_margin_mb() -> u64 {
+    1024
+}
+
+fn default_sync_interval_minutes() -> u64 {
+    30
+}
+
+fn default_sync_jitter_minutes() -> u64 {
+    5
+}
+
+fn default_max_sync_attempts() -> u32 {
+    5
+}
+
+fn default_retry_backoff_minutes() -> i64 {
+    15
+}
+
+fn default_max_deletions() -> usize {
+    10
+}
+
+fn default_max_deletions_percent() -> usize {
+    50
+}
+
+fn default_deletion_grace_period_hours() -> i64 {
+    72
+}
+
 fn deserialize_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
 where
     D: de::Deserializer<'de>,
@@ -13,6 +50,50 @@ where
     }
 }
 
+fn deserialize_non_zero_u64<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let value: u64 = de::Deserialize::deserialize(deserializer)?;
+
+    match value {
+        0 => Err(de::Error::custom("Must be greater than zero")),
+        value => Ok(value),
+    }
+}
+
+fn deserialize_non_negative_i64<'de, D>(deserializer: D) -> Result<i64, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let value: i64 = de::Deserialize::deserialize(deserializer)?;
+
+    match value {
+        ..0 => Err(de::Error::custom("Must not be negative")),
+        value => Ok(value),
+    }
+}
+
+/// Parses time of day as `HH:MM` into minutes after midnight.
+fn deserialize_time_of_day<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let s: String = de::Deserialize::deserialize(deserializer)?;
+
+    let minutes = s.split_once(':').and_then(|(hours, minutes)| {
+        let hours = hours.parse::<u16>().ok().filter(|hours| *hours < 24)?;
+        let minutes = minutes
+            .parse::<u16>()
+            .ok()
+            .filter(|minutes| *minutes < 60)?;
+
+        Some(hours * 60 + minutes)
+    });
+
+    minutes.ok_or_else(|| de::Error::custom(format!("Expected time like 09:00, got {}", s)))
+}
+
 fn deserialize_option_i64<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
 where
     D: de::Deserializer<'de>,
@@ -61,24 +142,170 @@ pub struct TransmissionConfig {
     pub username: Option<String>,
     #[serde(default, rename = "trans_password")]
     pub password: Option<String>,
+    #[serde(skip)]
+    pub bandwidth_profile: Option<BandwidthProfile>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AltSpeedDays {
+    #[default]
+    All,
+    Weekdays,
+    Weekend,
+}
+
+/// Alternative ("turtle") speed limits transmission switches to by schedule,
+/// e.g. to throttle downloads during work hours. Speeds are in KB/s.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BandwidthProfile {
+    #[serde(rename = "trans_alt_speed_down")]
+    pub down: u32,
+    #[serde(rename = "trans_alt_speed_up")]
+    pub up: u32,
     #[serde(
-        default,
-        rename = "trans_dry_run",
-        deserialize_with = "deserialize_bool"
+        rename = "trans_alt_speed_begin",
+        deserialize_with = "deserialize_time_of_day"
     )]
-    pub dry_run: bool,
+    pub begin: u16,
+    #[serde(
+        rename = "trans_alt_speed_end",
+        deserialize_with = "deserialize_time_of_day"
+    )]
+    pub end: u16,
+    #[serde(default, rename = "trans_alt_speed_days")]
+    pub days: AltSpeedDays,
+}
+
+impl BandwidthProfile {
+    /// The profile is optional, but once `TRANS_ALT_SPEED_BEGIN` is set, the rest must be valid.
+    fn from_env() -> Result<Option<Self>, envy::Error> {
+        match std::env::var_os("TRANS_ALT_SPEED_BEGIN") {
+            Some(_) => envy::from_env().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Session settings that turn the scheduled alternative speed limits on.
+    pub fn session_settings(&self) -> SessionSettings {
+        SessionSettings {
+            alt_speed_down: Some(self.down),
+            alt_speed_up: Some(self.up),
+            alt_speed_time_enabled: Some(true),
+            alt_speed_time_begin: Some(self.begin),
+            alt_speed_time_end: Some(self.end),
+            alt_speed_time_day: Some(match self.days {
+                AltSpeedDays::All => alt_speed_days::ALL,
+                AltSpeedDays::Weekdays => alt_speed_days::WEEKDAYS,
+                AltSpeedDays::Weekend => alt_speed_days::WEEKEND,
+            }),
+            ..SessionSettings::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct QBittorrentConfig {
+    #[serde(rename = "qbt_url")]
+    pub url: String,
+    #[serde(rename = "qbt_download_directory")]
+    pub download_directory: String,
+    #[serde(rename = "qbt_username")]
+    pub username: String,
+    #[serde(rename = "qbt_password")]
+    pub password: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadClientKind {
+    #[default]
+    Transmission,
+    QBittorrent,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Sled,
+    Sqlite,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanFormat {
+    #[default]
+    Text,
+    Json,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
     pub storage_file: String,
+    #[serde(default)]
+    pub storage_backend: StorageBackend,
     pub server_endpoint: String,
     #[serde(default)]
     pub wipeout_mode: bool,
+    #[serde(default)]
+    pub download_client: DownloadClientKind,
+    #[serde(
+        default,
+        alias = "trans_dry_run",
+        deserialize_with = "deserialize_bool"
+    )]
+    pub dry_run: bool,
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub plan_only: bool,
+    #[serde(default)]
+    pub plan_format: PlanFormat,
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub print_report: bool,
+    #[serde(default)]
+    pub show_reports: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub daemon_mode: bool,
+    #[serde(
+        default = "default_sync_interval_minutes",
+        deserialize_with = "deserialize_non_zero_u64"
+    )]
+    pub sync_interval_minutes: u64,
+    #[serde(default = "default_sync_jitter_minutes")]
+    pub sync_jitter_minutes: u64,
+    #[serde(default = "default_max_sync_attempts")]
+    pub max_sync_attempts: u32,
+    #[serde(
+        default = "default_retry_backoff_minutes",
+        deserialize_with = "deserialize_non_negative_i64"
+    )]
+    pub retry_backoff_minutes: i64,
+    #[serde(default = "default_max_deletions")]
+    pub max_deletions: usize,
+    #[serde(default = "default_max_deletions_percent")]
+    pub max_deletions_percent: usize,
+    #[serde(
+        default = "default_deletion_grace_period_hours",
+        deserialize_with = "deserialize_non_negative_i64"
+    )]
+    pub deletion_grace_period_hours: i64,
+    #[serde(
+        default = "default_free_space_margin_mb",
+        alias = "trans_free_space_margin_mb"
+    )]
+    pub free_space_margin_mb: u64,
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub notification_digest: bool,
     #[serde(flatten)]
     pub toloka: TolokaCredentials,
-    #[serde(flatten)]
-    pub transmission: TransmissionConfig,
+    #[serde(skip)]
+    pub transmission: Option<TransmissionConfig>,
+    #[serde(skip)]
+    pub qbittorrent: Option<QBittorrentConfig>,
+    #[serde(skip)]
+    pub file_selection: FileSelectionConfig,
+    #[serde(skip)]
+    pub path_templates: PathTemplates,
 }
 
 impl Config {
@@ -86,10 +313,122 @@ impl Config {
         dotenv::dotenv().ok();
     }
 
-    pub fn from_env() -> Self {
-        match envy::from_env::<Self>() {
-            Ok(config) => config,
-            Err(error) => panic!("Missing environment variable: {:#?}", error),
+    pub fn from_env() -> Result<Self, envy::Error> {
+        envy::from_env::<Self>().and_then(|config| {
+            let (transmission, qbittorrent) = match config.download_client {
+                DownloadClientKind::Transmission => {
+                    let transmission = TransmissionConfig {
+                        bandwidth_profile: BandwidthProfile::from_env()?,
+                        ..envy::from_env()?
+                    };
+
+                    (Some(transmission), None)
+                }
+                DownloadClientKind::QBittorrent => (None, Some(envy::from_env()?)),
+            };
+
+            Ok(Self {
+                transmission,
+                qbittorrent,
+                file_selection: FileSelectionConfig::from_env()?,
+                path_templates: PathTemplates::from_env()?,
+                ..config
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(extra: &[(&str, &str)]) -> Vec<(String, String)> {
+        [
+            ("STORAGE_FILE", "db"),
+            ("SERVER_ENDPOINT", "http://localhost"),
+            ("TOLOKA_USERNAME", "user"),
+            ("TOLOKA_PASSWORD", "secret"),
+        ]
+        .iter()
+        .chain(extra)
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn test_zero_sync_interval_is_rejected() {
+        let config = envy::from_iter::<_, Config>(env(&[("SYNC_INTERVAL_MINUTES", "0")]));
+
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_sync_interval() {
+        let config = envy::from_iter::<_, Config>(env(&[])).unwrap();
+        assert_eq!(config.sync_interval_minutes, 30);
+
+        let config = envy::from_iter::<_, Config>(env(&[("SYNC_INTERVAL_MINUTES", "10")])).unwrap();
+        assert_eq!(config.sync_interval_minutes, 10);
+    }
+
+    #[test]
+    fn test_negative_retry_backoff_is_rejected() {
+        let config = envy::from_iter::<_, Config>(env(&[("RETRY_BACKOFF_MINUTES", "-1")]));
+
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_negative_deletion_grace_period_is_rejected() {
+        let config = envy::from_iter::<_, Config>(env(&[("DELETION_GRACE_PERIOD_HOURS", "-1")]));
+        assert!(config.is_err());
+
+        let config =
+            envy::from_iter::<_, Config>(env(&[("DELETION_GRACE_PERIOD_HOURS", "0")])).unwrap();
+        assert_eq!(config.deletion_grace_period_hours, 0);
+    }
+
+    #[test]
+    fn test_bandwidth_profile() {
+        let profile = envy::from_iter::<_, BandwidthProfile>(env(&[
+            ("TRANS_ALT_SPEED_DOWN", "500"),
+            ("TRANS_ALT_SPEED_UP", "50"),
+            ("TRANS_ALT_SPEED_BEGIN", "09:00"),
+            ("TRANS_ALT_SPEED_END", "18:30"),
+            ("TRANS_ALT_SPEED_DAYS", "weekdays"),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            profile.session_settings(),
+            SessionSettings {
+                alt_speed_down: Some(500),
+                alt_speed_up: Some(50),
+                alt_speed_time_enabled: Some(true),
+                alt_speed_time_begin: Some(540),
+                alt_speed_time_end: Some(1110),
+                alt_speed_time_day: Some(alt_speed_days::WEEKDAYS),
+                ..SessionSettings::default()
+            }
+        );
+
+        for time in ["9", "24:00", "09:60", "nine"] {
+            let profile = envy::from_iter::<_, BandwidthProfile>(env(&[
+                ("TRANS_ALT_SPEED_DOWN", "500"),
+                ("TRANS_ALT_SPEED_UP", "50"),
+                ("TRANS_ALT_SPEED_BEGIN", time),
+                ("TRANS_ALT_SPEED_END", "18:30"),
+            ]));
+
+            assert!(profile.is_err());
         }
     }
+
+    #[test]
+    fn test_legacy_free_space_margin() {
+        let config =
+            envy::from_iter::<_, Config>(env(&[("TRANS_FREE_SPACE_MARGIN_MB", "10")])).unwrap();
+
+        assert_eq!(config.free_space_margin_mb, 10);
+    }
 }
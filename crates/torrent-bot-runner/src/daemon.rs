@@ -0,0 +1,122 @@
+use std::future::Future;
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::info;
+
+/// Tells long-running work to stop after the current step.
+#[derive(Clone)]
+pub(crate) struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Requests shutdown on SIGTERM or SIGINT.
+    pub(crate) fn listen() -> Self {
+        let (sender, receiver) = watch::channel(false);
+
+        actix_rt::spawn(async move {
+            let mut terminate = signal(SignalKind::terminate()).expect("Unable to listen SIGTERM");
+            let mut interrupt = signal(SignalKind::interrupt()).expect("Unable to listen SIGINT");
+
+            tokio::select! {
+                _ = terminate.recv() => info!("Got SIGTERM, shutting down..."),
+                _ = interrupt.recv() => info!("Got SIGINT, shutting down..."),
+            }
+
+            let _ = sender.send(true);
+        });
+
+        Self { receiver }
+    }
+
+    pub(crate) fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Sleeps for `duration`, waking up early on shutdown.
+    /// Returns `false` if shutdown was requested.
+    pub(crate) async fn sleep(&mut self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = actix_rt::time::sleep(duration) => true,
+            Ok(_) = self.receiver.wait_for(|requested| *requested) => false,
+        }
+    }
+
+    /// Runs `future`, dropping it as soon as shutdown is requested.
+    /// Returns `None` if shutdown was requested.
+    pub(crate) async fn interruptible<F: Future>(&self, future: F) -> Option<F::Output> {
+        let mut receiver = self.receiver.clone();
+
+        tokio::select! {
+            output = future => Some(output),
+            Ok(_) = receiver.wait_for(|requested| *requested) => None,
+        }
+    }
+}
+
+/// Interval between syncs in daemon mode. A random jitter is added to every interval,
+/// so requests to toloka don't happen at the same time every run.
+pub(crate) struct Schedule {
+    pub(crate) interval: Duration,
+    pub(crate) jitter: Duration,
+}
+
+impl Schedule {
+    pub(crate) fn next_delay(&self) -> Duration {
+        self.interval + rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_interruptible_stops_on_shutdown() {
+        let (sender, receiver) = watch::channel(false);
+        let shutdown = Shutdown { receiver };
+
+        sender.send(true).unwrap();
+        let output = shutdown
+            .interruptible(actix_rt::time::sleep(Duration::from_secs(60)))
+            .await;
+
+        assert!(output.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_interruptible_without_shutdown() {
+        let (_sender, receiver) = watch::channel(false);
+        let shutdown = Shutdown { receiver };
+
+        assert_eq!(shutdown.interruptible(async { 42 }).await, Some(42));
+    }
+
+    #[test]
+    fn test_next_delay_is_within_jitter() {
+        let schedule = Schedule {
+            interval: Duration::from_secs(60),
+            jitter: Duration::from_secs(10),
+        };
+
+        for _ in 0..100 {
+            let delay = schedule.next_delay();
+
+            assert!(delay >= Duration::from_secs(60));
+            assert!(delay <= Duration::from_secs(70));
+        }
+    }
+
+    #[test]
+    fn test_next_delay_without_jitter() {
+        let schedule = Schedule {
+            interval: Duration::from_secs(60),
+            jitter: Duration::ZERO,
+        };
+
+        assert_eq!(schedule.next_delay(), Duration::from_secs(60));
+    }
+}
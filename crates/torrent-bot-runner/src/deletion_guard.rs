@@ -0,0 +1,64 @@
+/// Protects the library from a broken watched list: toloka answering with an empty or
+/// truncated page makes every task look unwatched, and all of them would be deleted.
+#[derive(Debug, Clone)]
+pub(crate) struct DeletionGuard {
+    /// Most deletions allowed in one run.
+    pub(crate) max_count: usize,
+    /// Most deletions allowed in one run, in percent of all tasks.
+    /// A single deletion is always allowed, so one topic can be unwatched in a small library.
+    pub(crate) max_percent: usize,
+    /// Explicit override that lets any number of deletions through.
+    pub(crate) allow_mass_deletion: bool,
+}
+
+impl DeletionGuard {
+    /// Whether deleting `deletions` of `tasks_count` tasks in one run is allowed.
+    pub(crate) fn allows(&self, deletions: usize, tasks_count: usize) -> bool {
+        if self.allow_mass_deletion || deletions <= 1 {
+            return true;
+        }
+
+        deletions <= self.max_count && deletions * 100 <= self.max_percent * tasks_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard() -> DeletionGuard {
+        DeletionGuard {
+            max_count: 5,
+            max_percent: 50,
+            allow_mass_deletion: false,
+        }
+    }
+
+    #[test]
+    fn test_allows_few_deletions() {
+        assert!(guard().allows(0, 0));
+        assert!(guard().allows(1, 1));
+        assert!(guard().allows(5, 10));
+    }
+
+    #[test]
+    fn test_blocks_too_many_deletions() {
+        assert!(!guard().allows(6, 100));
+    }
+
+    #[test]
+    fn test_blocks_too_large_share_of_deletions() {
+        assert!(!guard().allows(2, 3));
+        assert!(!guard().allows(4, 4));
+    }
+
+    #[test]
+    fn test_override_allows_everything() {
+        let guard = DeletionGuard {
+            allow_mass_deletion: true,
+            ..guard()
+        };
+
+        assert!(guard.allows(100, 100));
+    }
+}
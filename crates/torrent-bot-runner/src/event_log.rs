@@ -0,0 +1,190 @@
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::task_db::{Task, TorrentId};
+
+/// What happened to a topic.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum EventKind {
+    Added,
+    Updated,
+    Finished,
+    Deleted,
+    Failed,
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            EventKind::Added => "added",
+            EventKind::Updated => "updated",
+            EventKind::Finished => "finished",
+            EventKind::Deleted => "deleted",
+            EventKind::Failed => "failed",
+        };
+
+        f.pad(name)
+    }
+}
+
+/// Entry of the event log. Unlike tasks, events are kept after the topic is deleted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Event {
+    pub(crate) at: DateTime<Utc>,
+    pub(crate) kind: EventKind,
+    pub(crate) topic_id: String,
+    pub(crate) title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) torrent_hash: Option<String>,
+    /// Why the sync of the topic failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+impl Event {
+    pub(crate) fn new(kind: EventKind, topic_id: &str, title: &str) -> Self {
+        Self {
+            at: Utc::now(),
+            kind,
+            topic_id: topic_id.to_string(),
+            title: title.to_string(),
+            torrent_hash: None,
+            error: None,
+        }
+    }
+
+    /// Event of the topic, with the torrent hash taken from its task.
+    pub(crate) fn of_task(kind: EventKind, task: &Task) -> Self {
+        Self {
+            torrent_hash: torrent_hash(task),
+            ..Self::new(kind, &task.topic_id, &task.topic_title)
+        }
+    }
+}
+
+/// Info hash of the task's torrent. Tasks added before hashes were stored
+/// only have it if the download client identifies torrents by hash.
+pub(crate) fn torrent_hash(task: &Task) -> Option<String> {
+    match (&task.torrent_hash, &task.transmission_torrent_id) {
+        (Some(hash), _) => Some(hash.clone()),
+        (None, Some(TorrentId::Hash(hash))) => Some(hash.clone()),
+        (None, _) => None,
+    }
+}
+
+/// Which events to read from the log. Both ends of the time range are inclusive.
+#[derive(Debug, Default)]
+pub(crate) struct EventFilter {
+    pub(crate) since: Option<DateTime<Utc>>,
+    pub(crate) until: Option<DateTime<Utc>>,
+    pub(crate) topic_id: Option<String>,
+    pub(crate) kind: Option<EventKind>,
+}
+
+impl EventFilter {
+    pub(crate) fn matches(&self, event: &Event) -> bool {
+        self.since.is_none_or(|since| event.at >= since)
+            && self.until.is_none_or(|until| event.at <= until)
+            && self
+                .topic_id
+                .as_ref()
+                .is_none_or(|topic_id| &event.topic_id == topic_id)
+            && self.kind.is_none_or(|kind| event.kind == kind)
+    }
+}
+
+/// Parses time given on the command line, either RFC 3339 (`2024-06-01T22:00:00Z`)
+/// or how long ago, in minutes, hours or days (`30m`, `12h`, `7d`).
+pub(crate) fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let invalid = || format!("Expected RFC 3339 time or duration like 12h, got {}", value);
+    let unit_at = value.len().checked_sub(1).ok_or_else(invalid)?;
+    let (amount, unit) = value.split_at(unit_at);
+    let amount = amount.parse::<i64>().map_err(|_| invalid())?;
+    let ago = match unit {
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        _ => None,
+    }
+    .ok_or_else(invalid)?;
+
+    Utc::now().checked_sub_signed(ago).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: EventKind, topic_id: &str) -> Event {
+        Event::new(kind, topic_id, &format!("Topic {}", topic_id))
+    }
+
+    #[test]
+    fn test_parses_time() {
+        assert_eq!(
+            parse_time("2024-06-01T22:00:00+03:00").unwrap(),
+            "2024-06-01T19:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        let twelve_hours_ago = Utc::now() - Duration::hours(12);
+        let parsed = parse_time("12h").unwrap();
+        assert!((parsed - twelve_hours_ago).num_seconds().abs() < 5);
+
+        assert!(parse_time("").is_err());
+        assert!(parse_time("12").is_err());
+        assert!(parse_time("12w").is_err());
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_filters_events() {
+        let filter = EventFilter {
+            topic_id: Some("1".to_string()),
+            kind: Some(EventKind::Deleted),
+            ..EventFilter::default()
+        };
+
+        assert!(filter.matches(&event(EventKind::Deleted, "1")));
+        assert!(!filter.matches(&event(EventKind::Deleted, "2")));
+        assert!(!filter.matches(&event(EventKind::Added, "1")));
+
+        let filter = EventFilter {
+            until: Some(Utc::now() - Duration::hours(1)),
+            ..EventFilter::default()
+        };
+
+        assert!(!filter.matches(&event(EventKind::Added, "1")));
+    }
+
+    #[test]
+    fn test_takes_torrent_hash_from_task() {
+        let mut task: Task = serde_json::from_value(serde_json::json!({
+            "topic_id": "1",
+            "topic_title": "Topic 1",
+            "topic_download_registered_at": "2024-01-01",
+            "transmission_torrent_id": 5,
+        }))
+        .unwrap();
+
+        assert_eq!(Event::of_task(EventKind::Added, &task).torrent_hash, None);
+
+        task.transmission_torrent_id = Some(TorrentId::Hash("abc".to_string()));
+        assert_eq!(
+            Event::of_task(EventKind::Added, &task).torrent_hash,
+            Some("abc".to_string())
+        );
+
+        task.torrent_hash = Some("def".to_string());
+        assert_eq!(
+            Event::of_task(EventKind::Added, &task).torrent_hash,
+            Some("def".to_string())
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{HashMap, HashSet};
+
+use torrent_bot_clients::download_client::TorrentFile;
+
+/// Rename of a file or directory inside a torrent: the last component of `path` becomes `name`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Rename {
+    pub(crate) path: String,
+    pub(crate) name: String,
+}
+
+/// Steps that make the files of an old torrent match the layout of its new version,
+/// so they don't have to be downloaded again.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct LayoutUpgrade {
+    pub(crate) renames: Vec<Rename>,
+    /// Bytes of the new torrent that are already present in the old one.
+    pub(crate) reused_bytes: u64,
+}
+
+/// Returns top level directory shared by all files, if there is one.
+fn root_dir(files: &[TorrentFile]) -> Option<&str> {
+    let (root, _) = files.first()?.name.split_once('/')?;
+
+    files
+        .iter()
+        .all(|file| {
+            file.name
+                .split_once('/')
+                .is_some_and(|(file_root, _)| file_root == root)
+        })
+        .then_some(root)
+}
+
+fn split_parent(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+/// Plans renames of the old torrent's files to the layout of the new torrent.
+///
+/// The top level directory is renamed first, if it changed. Then files that are missing
+/// from the new layout are renamed to a new file of the same size in the same directory.
+/// Files moved to other directories can't be renamed in place and will be downloaded again.
+pub(crate) fn plan_layout_upgrade(
+    old_files: &[TorrentFile],
+    new_files: &[TorrentFile],
+) -> LayoutUpgrade {
+    let mut upgrade = LayoutUpgrade::default();
+
+    let root_rename = match (root_dir(old_files), root_dir(new_files)) {
+        (Some(old_root), Some(new_root)) if old_root != new_root => {
+            upgrade.renames.push(Rename {
+                path: old_root.to_string(),
+                name: new_root.to_string(),
+            });
+            Some((old_root, new_root))
+        }
+        _ => None,
+    };
+
+    let old_files = old_files
+        .iter()
+        .map(|file| match root_rename {
+            Some((old_root, new_root)) => (
+                format!("{}{}", new_root, &file.name[old_root.len()..]),
+                file.length,
+            ),
+            None => (file.name.clone(), file.length),
+        })
+        .collect::<Vec<_>>();
+
+    let old_paths = old_files
+        .iter()
+        .map(|(name, length)| (name.as_str(), *length))
+        .collect::<HashMap<_, _>>();
+    let new_paths = new_files
+        .iter()
+        .map(|file| file.name.as_str())
+        .collect::<HashSet<_>>();
+
+    let mut unmatched_new_files = vec![];
+    for file in new_files {
+        match old_paths.get(file.name.as_str()) {
+            Some(length) if *length == file.length => upgrade.reused_bytes += file.length,
+            Some(_) => (),
+            None => unmatched_new_files.push(file),
+        }
+    }
+
+    for (old_name, old_length) in old_files.iter() {
+        if new_paths.contains(old_name.as_str()) {
+            continue;
+        }
+
+        let (old_parent, _) = split_parent(old_name);
+        let candidates = unmatched_new_files
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| {
+                file.length == *old_length && split_parent(&file.name).0 == old_parent
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+
+        // Several files of the same size can't be told apart, so those are left alone.
+        if let [index] = candidates[..] {
+            let new_file = unmatched_new_files.remove(index);
+
+            upgrade.renames.push(Rename {
+                path: old_name.clone(),
+                name: split_parent(&new_file.name).1.to_string(),
+            });
+            upgrade.reused_bytes += new_file.length;
+        }
+    }
+
+    upgrade
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(files: &[(&str, u64)]) -> Vec<TorrentFile> {
+        files
+            .iter()
+            .enumerate()
+            .map(|(index, (name, length))| TorrentFile {
+                index,
+                name: name.to_string(),
+                length: *length,
+            })
+            .collect()
+    }
+
+    fn rename(path: &str, name: &str) -> Rename {
+        Rename {
+            path: path.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_unchanged_layout() {
+        let old_files = files(&[("Show/E01.mkv", 10), ("Show/E02.mkv", 20)]);
+        let new_files = files(&[
+            ("Show/E01.mkv", 10),
+            ("Show/E02.mkv", 20),
+            ("Show/E03.mkv", 30),
+        ]);
+
+        assert_eq!(
+            plan_layout_upgrade(&old_files, &new_files),
+            LayoutUpgrade {
+                renames: vec![],
+                reused_bytes: 30,
+            }
+        );
+    }
+
+    #[test]
+    fn test_renamed_root_directory() {
+        let old_files = files(&[("Show (1-4)/E01.mkv", 10), ("Show (1-4)/E02.mkv", 20)]);
+        let new_files = files(&[
+            ("Show (1-6)/E01.mkv", 10),
+            ("Show (1-6)/E02.mkv", 20),
+            ("Show (1-6)/E03.mkv", 30),
+        ]);
+
+        assert_eq!(
+            plan_layout_upgrade(&old_files, &new_files),
+            LayoutUpgrade {
+                renames: vec![rename("Show (1-4)", "Show (1-6)")],
+                reused_bytes: 30,
+            }
+        );
+    }
+
+    #[test]
+    fn test_renamed_files() {
+        let old_files = files(&[
+            ("Show (1-4)/Show.S01E01.720p.mkv", 10),
+            ("Show (1-4)/Show.S01E02.720p.mkv", 20),
+            ("Show (1-4)/Subs/E01.srt", 1),
+        ]);
+        let new_files = files(&[
+            ("Show/Show.S01E01.mkv", 10),
+            ("Show/Show.S01E02.mkv", 20),
+            ("Show/Show.S01E03.mkv", 30),
+            ("Show/E01.srt", 1),
+        ]);
+
+        assert_eq!(
+            plan_layout_upgrade(&old_files, &new_files),
+            LayoutUpgrade {
+                renames: vec![
+                    rename("Show (1-4)", "Show"),
+                    rename("Show/Show.S01E01.720p.mkv", "Show.S01E01.mkv"),
+                    rename("Show/Show.S01E02.720p.mkv", "Show.S01E02.mkv"),
+                ],
+                reused_bytes: 30,
+            }
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_files_are_not_renamed() {
+        let old_files = files(&[("Show/a.mkv", 10)]);
+        let new_files = files(&[("Show/b.mkv", 10), ("Show/c.mkv", 10)]);
+
+        assert_eq!(
+            plan_layout_upgrade(&old_files, &new_files),
+            LayoutUpgrade::default()
+        );
+    }
+
+    #[test]
+    fn test_single_file_torrents() {
+        let old_files = files(&[("Movie.720p.mkv", 10)]);
+        let new_files = files(&[("Movie.1080p.mkv", 10)]);
+
+        assert_eq!(
+            plan_layout_upgrade(&old_files, &new_files),
+            LayoutUpgrade {
+                renames: vec![rename("Movie.720p.mkv", "Movie.1080p.mkv")],
+                reused_bytes: 10,
+            }
+        );
+    }
+
+    #[test]
+    fn test_changed_file_is_not_reused() {
+        let old_files = files(&[("Show/E01.mkv", 10)]);
+        let new_files = files(&[("Show/E01.mkv", 11)]);
+
+        assert_eq!(
+            plan_layout_upgrade(&old_files, &new_files),
+            LayoutUpgrade::default()
+        );
+    }
+}
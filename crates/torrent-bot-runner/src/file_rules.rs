@@ -0,0 +1,164 @@
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+use tracing::warn;
+
+use torrent_bot_clients::download_client::TorrentFile;
+use torrent_bot_clients::toloka::types::Category;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct FileRules {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub max_size: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FileSelectionConfig {
+    pub movies: FileRules,
+    pub series: FileRules,
+    pub other: FileRules,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct FileSelection<'a> {
+    pub(crate) wanted: Vec<&'a TorrentFile>,
+    pub(crate) unwanted: Vec<&'a TorrentFile>,
+}
+
+impl FileSelectionConfig {
+    pub fn from_env() -> Result<Self, envy::Error> {
+        Ok(Self {
+            movies: envy::prefixed("MOVIES_FILES_").from_env()?,
+            series: envy::prefixed("SERIES_FILES_").from_env()?,
+            other: envy::prefixed("OTHER_FILES_").from_env()?,
+        })
+    }
+
+    pub(crate) fn for_category(&self, category: &Category) -> &FileRules {
+        match category {
+            Category::Movies => &self.movies,
+            Category::Series => &self.series,
+            Category::Other(_) => &self.other,
+        }
+    }
+}
+
+fn matches_any(patterns: &[String], name: &str) -> bool {
+    patterns.iter().any(|pattern| match Pattern::new(pattern) {
+        Ok(pattern) => pattern.matches_with(name, MATCH_OPTIONS),
+        Err(error) => {
+            warn!(?error, pattern, "Invalid file pattern. Ignoring...");
+            false
+        }
+    })
+}
+
+impl FileRules {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty() && self.max_size.is_none()
+    }
+
+    fn is_wanted(&self, file: &TorrentFile) -> bool {
+        let included = self.include.is_empty() || matches_any(&self.include, &file.name);
+        let excluded = matches_any(&self.exclude, &file.name);
+        let fits = self.max_size.is_none_or(|max_size| file.length <= max_size);
+
+        included && !excluded && fits
+    }
+
+    /// Splits torrent files into wanted and unwanted ones.
+    /// If the rules reject every file, all files are kept wanted, since that almost
+    /// certainly means the rules don't fit the release rather than an empty download.
+    pub(crate) fn select<'a>(&self, files: &'a [TorrentFile]) -> FileSelection<'a> {
+        let (wanted, unwanted): (Vec<_>, Vec<_>) = files.iter().partition(|f| self.is_wanted(f));
+
+        if wanted.is_empty() && !unwanted.is_empty() {
+            warn!("File rules reject every file in torrent. Keeping all files...");
+
+            return FileSelection {
+                wanted: files.iter().collect(),
+                unwanted: vec![],
+            };
+        }
+
+        FileSelection { wanted, unwanted }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(index: usize, name: &str, length: u64) -> TorrentFile {
+        TorrentFile {
+            index,
+            name: name.to_string(),
+            length,
+        }
+    }
+
+    fn names(files: &[&TorrentFile]) -> Vec<String> {
+        files.iter().map(|f| f.name.clone()).collect()
+    }
+
+    #[test]
+    fn test_empty_rules_want_everything() {
+        let files = vec![file(0, "Show/E01.mkv", 100), file(1, "Show/sample.mkv", 10)];
+        let selection = FileRules::default().select(&files);
+
+        assert_eq!(selection.wanted.len(), 2);
+        assert!(selection.unwanted.is_empty());
+    }
+
+    #[test]
+    fn test_include_exclude_and_max_size() {
+        let rules = FileRules {
+            include: vec!["*.mkv".to_string(), "*.mka".to_string()],
+            exclude: vec!["*sample*".to_string()],
+            max_size: Some(1000),
+        };
+        let files = vec![
+            file(0, "Show/E01.MKV", 500),
+            file(1, "Show/Sample/E01.mkv", 50),
+            file(2, "Show/Extras/readme.txt", 1),
+            file(3, "Show/Audio/E01.Eng.mka", 100),
+            file(4, "Show/E02.mkv", 5000),
+        ];
+        let selection = rules.select(&files);
+
+        assert_eq!(
+            names(&selection.wanted),
+            vec!["Show/E01.MKV", "Show/Audio/E01.Eng.mka"]
+        );
+        assert_eq!(
+            names(&selection.unwanted),
+            vec![
+                "Show/Sample/E01.mkv",
+                "Show/Extras/readme.txt",
+                "Show/E02.mkv"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rules_rejecting_everything_keep_all_files() {
+        let rules = FileRules {
+            include: vec!["*.avi".to_string()],
+            ..FileRules::default()
+        };
+        let files = vec![file(0, "Movie.mkv", 100)];
+        let selection = rules.select(&files);
+
+        assert_eq!(selection.wanted.len(), 1);
+        assert!(selection.unwanted.is_empty());
+    }
+}
@@ -1,56 +1,303 @@
-use tracing::{error, Level};
+use std::time::Duration;
+
+use clap::Parser;
+
+use tracing::{error, info, Level};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::FmtSubscriber;
 
+use torrent_bot_clients::download_client::{DownloadClient, DownloadClientError};
+use torrent_bot_clients::qbittorrent::QBittorrentClient;
 use torrent_bot_clients::toloka::TolokaClient;
 use torrent_bot_clients::transmission::TransmissionClient;
 
+use crate::cli::{
+    run_check, run_db_command, run_events_command, run_tasks_command, Cli, Command, CommandError,
+    DbCommand, SyncArgs,
+};
 use crate::client::Client;
-use crate::config::Config;
-use crate::sync_v2::sync;
-use crate::task_db::TaskDb;
+use crate::config::{Config, DownloadClientKind, PlanFormat};
+use crate::daemon::{Schedule, Shutdown};
+use crate::deletion_guard::DeletionGuard;
+use crate::retry::RetryPolicy;
+use crate::report::Timings;
+use crate::sync_plan::plan_sync;
+use crate::sync_v2::{sync, SyncOptions};
+use crate::task_db::{open_task_db, open_task_db_lenient, TaskDb};
 
+mod cli;
 mod client;
 mod config;
+mod daemon;
+mod deletion_guard;
+mod event_log;
+mod file_layout;
+mod file_rules;
+mod migrations;
+mod notification;
+mod path_template;
+mod report;
+mod retry;
+mod sled_task_db;
+mod sqlite_task_db;
 mod sync_extensions;
+mod sync_plan;
 mod sync_v2;
 mod task_db;
+mod topic_title;
+
+async fn create_download_client(config: &Config) -> Result<Box<dyn DownloadClient>, CommandError> {
+    match config.download_client {
+        DownloadClientKind::Transmission => {
+            let transmission = config
+                .transmission
+                .clone()
+                .ok_or(CommandError::MissingConfig("transmission"))?;
+
+            Ok(Box::new(TransmissionClient::create(
+                transmission.url,
+                transmission.username,
+                transmission.password,
+                Some(transmission.download_directory),
+                config.dry_run,
+            )))
+        }
+        DownloadClientKind::QBittorrent => {
+            let qbittorrent = config
+                .qbittorrent
+                .clone()
+                .ok_or(CommandError::MissingConfig("qBittorrent"))?;
+
+            Ok(Box::new(
+                QBittorrentClient::create(
+                    &qbittorrent.url,
+                    &qbittorrent.username,
+                    &qbittorrent.password,
+                    Some(qbittorrent.download_directory),
+                    config.dry_run,
+                )
+                .await
+                .map_err(DownloadClientError::from)?,
+            ))
+        }
+    }
+}
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
+    let command = Cli::parse()
+        .command
+        .unwrap_or(Command::Sync(SyncArgs::default()));
     let config = {
         Config::init_dotenv();
-        Config::from_env()
+
+        match Config::from_env() {
+            Ok(config) => config,
+            Err(error) if matches!(command, Command::Check) => {
+                println!("[failed] config: {}", error);
+                std::process::exit(1);
+            }
+            Err(error) => {
+                eprintln!("Invalid config: {}", error);
+                std::process::exit(1);
+            }
+        }
     };
 
+    // Plans, reports and output of commands are printed to stdout,
+    // so logs must not be mixed into them.
+    let prints_output = !matches!(command, Command::Sync(_))
+        || config.plan_only
+        || config.print_report
+        || config.show_reports.is_some();
+    let writer = match prints_output {
+        true => BoxMakeWriter::new(std::io::stderr),
+        false => BoxMakeWriter::new(std::io::stdout),
+    };
     let subscriber = FmtSubscriber::builder()
         .with_max_level(Level::DEBUG)
+        .with_writer(writer)
         .finish();
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    let storage = TaskDb::create(&config.storage_file).expect("Unable to initialize DB");
-    let toloka_client = TolokaClient::create(&config.toloka.username, &config.toloka.password)
-        .await
-        .expect("Unable to initialize toloka client");
-    let transmission_client = TransmissionClient::create(
-        config.transmission.url,
-        config.transmission.username,
-        config.transmission.password,
-        Some(config.transmission.download_directory),
-        config.transmission.dry_run,
+    // Repair and export must work on a storage with unreadable tasks.
+    let open = match command {
+        Command::Db(DbCommand::Repair | DbCommand::Export { .. }) => open_task_db_lenient,
+        _ => open_task_db,
+    };
+    let storage = match open(&config.storage_backend, &config.storage_file) {
+        Ok(storage) => storage,
+        Err(error) => {
+            eprintln!("Unable to initialize DB: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let result = match command {
+        Command::Sync(args) => return run_sync(config, storage, args).await,
+        Command::Tasks(command) => run_tasks_command(storage.as_ref(), command),
+        Command::Events(args) => run_events_command(storage.as_ref(), args),
+        Command::Db(command) => run_db_command(storage.as_ref(), command),
+        Command::Check => {
+            let download_client = create_download_client(&config).await;
+            run_check(&config, storage.as_ref(), download_client).await
+        }
+    };
+
+    // Changes must be on disk before the process exits.
+    if let Err(error) = result.and_then(|()| Ok(storage.flush()?)) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Makes transmission switch to the speed limits of the bandwidth profile by its schedule.
+/// Downloads go on with the current limits if it fails.
+async fn apply_bandwidth_profile(config: &Config) {
+    let (Some(transmission), DownloadClientKind::Transmission) =
+        (&config.transmission, &config.download_client)
+    else {
+        return;
+    };
+    let Some(profile) = &transmission.bandwidth_profile else {
+        return;
+    };
+
+    let client = TransmissionClient::create(
+        transmission.url.clone(),
+        transmission.username.clone(),
+        transmission.password.clone(),
+        Some(transmission.download_directory.clone()),
+        config.dry_run,
     );
+
+    let settings = profile.session_settings();
+    match client.set_session_settings(&settings).await {
+        Ok(()) => info!(?profile, "Applied bandwidth profile"),
+        Err(error) => error!(?error, "Unable to apply bandwidth profile"),
+    }
+}
+
+/// Runs a sync, or keeps syncing in daemon mode.
+async fn run_sync(config: Config, storage: Box<dyn TaskDb>, args: SyncArgs) -> std::io::Result<()> {
+    if let Some(limit) = config.show_reports {
+        match storage.get_reports(limit) {
+            Ok(reports) => println!(
+                "{}",
+                serde_json::to_string_pretty(&reports).expect("Unable to serialize reports")
+            ),
+            Err(error) => error!("Unable to load sync reports: {:?}", error),
+        }
+
+        return Ok(());
+    }
+
+    let toloka_client =
+        match TolokaClient::create(&config.toloka.username, &config.toloka.password).await {
+            Ok(client) => client,
+            Err(error) => {
+                eprintln!("Unable to initialize toloka client: {}", error);
+                std::process::exit(1);
+            }
+        };
+    let download_client = match create_download_client(&config).await {
+        Ok(client) => client,
+        Err(error) => {
+            eprintln!("Unable to initialize download client: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let deletion_guard = DeletionGuard {
+        max_count: config.max_deletions,
+        max_percent: config.max_deletions_percent,
+        allow_mass_deletion: args.allow_mass_deletion,
+    };
+
+    let deletion_grace_period = chrono::Duration::hours(config.deletion_grace_period_hours);
+
+    if config.plan_only {
+        match plan_sync(
+            &toloka_client,
+            download_client.as_ref(),
+            storage.as_ref(),
+            config.wipeout_mode,
+            deletion_grace_period,
+            &deletion_guard,
+            &Timings::default(),
+        )
+        .await
+        {
+            Ok(plan) => match config.plan_format {
+                PlanFormat::Text => print!("{}", plan),
+                PlanFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&plan).expect("Unable to serialize plan")
+                ),
+            },
+            Err(error) => error!("Plan error: {:?}", error),
+        }
+
+        return Ok(());
+    }
+
+    apply_bandwidth_profile(&config).await;
+
     let client = Client::create(&config.server_endpoint);
+    let mut options = SyncOptions {
+        wipeout_mode: config.wipeout_mode,
+        file_selection: config.file_selection,
+        path_templates: config.path_templates,
+        free_space_margin: config.free_space_margin_mb * 1024 * 1024,
+        retry_policy: RetryPolicy {
+            max_attempts: config.max_sync_attempts,
+            backoff: chrono::Duration::minutes(config.retry_backoff_minutes),
+        },
+        deletion_guard: deletion_guard.clone(),
+        deletion_grace_period,
+        notification_digest: config.notification_digest,
+    };
+    let schedule = Schedule {
+        interval: Duration::from_secs(config.sync_interval_minutes * 60),
+        jitter: Duration::from_secs(config.sync_jitter_minutes * 60),
+    };
+    let mut shutdown = Shutdown::listen();
+
+    // Syncs run one after another, so they never overlap.
+    loop {
+        let report = sync(
+            &toloka_client,
+            download_client.as_ref(),
+            storage.as_ref(),
+            &client,
+            &options,
+            &shutdown,
+        )
+        .await;
+
+        // Mass deletion is allowed for one sync only, later ones are guarded again.
+        options.deletion_guard.allow_mass_deletion = false;
+
+        if config.print_report {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).expect("Unable to serialize report")
+            );
+        }
+
+        if !config.daemon_mode {
+            break;
+        }
+
+        let delay = schedule.next_delay();
+        info!("Next sync in {} seconds", delay.as_secs());
 
-    if let Err(error) = sync(
-        toloka_client,
-        transmission_client,
-        storage,
-        client,
-        config.wipeout_mode,
-    )
-    .await
-    {
-        error!("Sync error: {:?}", error);
+        if !shutdown.sleep(delay).await {
+            break;
+        }
     }
 
     Ok(())
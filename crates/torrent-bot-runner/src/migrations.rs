@@ -0,0 +1,232 @@
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+use tracing::info;
+
+use crate::sled_task_db::TASKS_TREE;
+use crate::task_db::{StorageError, StorageResult};
+
+/// Key of the schema version in the default tree, stored as big endian `u32`.
+/// Storage without it has version 0.
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// Key all tasks were stored under as a single list in version 0.
+const TASKS_KEY: &str = "torrent_bot_tasks";
+
+/// Single step of the storage schema, from `version - 1` to `version`.
+///
+/// Migrations work with raw JSON instead of the current types, so they keep working
+/// when the types change. The version is saved after the migration, so a migration
+/// must be safe to run again if the runner stops in between.
+pub(crate) struct Migration {
+    pub(crate) version: u32,
+    pub(crate) description: &'static str,
+    pub(crate) migrate: fn(&sled::Db) -> StorageResult<()>,
+}
+
+/// All migrations, in order.
+pub(crate) const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "Move tasks to a key per topic",
+    migrate: move_tasks_to_tree,
+}];
+
+pub(crate) fn read_version(db: &sled::Db) -> StorageResult<u32> {
+    match db.get(SCHEMA_VERSION_KEY)? {
+        Some(raw) => {
+            let bytes = raw
+                .as_ref()
+                .try_into()
+                .map_err(|_| StorageError::InvalidSchemaVersion)?;
+
+            Ok(u32::from_be_bytes(bytes))
+        }
+        None => Ok(0),
+    }
+}
+
+fn write_version(db: &sled::Db, version: u32) -> StorageResult<()> {
+    db.insert(SCHEMA_VERSION_KEY, &version.to_be_bytes())?;
+    Ok(())
+}
+
+/// Brings the storage to the latest version of `migrations`.
+/// Fails if the storage was written by a newer runner.
+pub(crate) fn migrate(db: &sled::Db, migrations: &[Migration]) -> StorageResult<()> {
+    let version = read_version(db)?;
+    let supported = migrations.last().map_or(0, |migration| migration.version);
+
+    if version > supported {
+        return Err(StorageError::UnsupportedSchemaVersion { version, supported });
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > version) {
+        info!(
+            version = migration.version,
+            "Migrating storage: {}", migration.description
+        );
+
+        (migration.migrate)(db)?;
+        write_version(db, migration.version)?;
+    }
+
+    db.flush()?;
+
+    Ok(())
+}
+
+/// Version 1: tasks are moved from a single list to a key per topic, in one transaction.
+fn move_tasks_to_tree(db: &sled::Db) -> StorageResult<()> {
+    let Some(raw) = db.get(TASKS_KEY)? else {
+        return Ok(());
+    };
+
+    let tasks: Vec<serde_json::Value> =
+        serde_json::from_slice(raw.as_ref()).map_err(StorageError::InvalidTaskList)?;
+    let tasks = tasks
+        .into_iter()
+        .map(
+            |task| match task.get("topic_id").and_then(|id| id.as_str()) {
+                Some(topic_id) => Ok((topic_id.to_string(), serde_json::to_vec(&task).unwrap())),
+                None => Err(StorageError::InvalidTaskList(
+                    serde::de::Error::missing_field("topic_id"),
+                )),
+            },
+        )
+        .collect::<StorageResult<Vec<_>>>()?;
+
+    let tree = db.open_tree(TASKS_TREE)?;
+    let default: &sled::Tree = db;
+
+    (default, &tree)
+        .transaction(|(default, tree)| {
+            for (topic_id, task) in tasks.iter() {
+                tree.insert(topic_id.as_bytes(), task.as_slice())?;
+            }
+            default.remove(TASKS_KEY)?;
+
+            Ok::<_, ConflictableTransactionError<()>>(())
+        })
+        .map_err(|error| match error {
+            TransactionError::Storage(error) => StorageError::from(error),
+            TransactionError::Abort(()) => unreachable!("Migration is never aborted"),
+        })?;
+
+    info!(count = tasks.len(), "Moved tasks to a key per topic");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn temporary_db() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    #[test]
+    fn test_migrates_from_version_0() {
+        let db = temporary_db();
+        db.insert(
+            TASKS_KEY,
+            serde_json::to_vec(&serde_json::json!([
+                {"topic_id": "1", "topic_title": "Topic 1"},
+                {"topic_id": "2", "topic_title": "Topic 2"},
+            ]))
+            .unwrap(),
+        )
+        .unwrap();
+
+        migrate(&db, MIGRATIONS).unwrap();
+
+        let tree = db.open_tree(TASKS_TREE).unwrap();
+        let topic_ids = tree
+            .iter()
+            .keys()
+            .map(|key| String::from_utf8(key.unwrap().to_vec()).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(topic_ids, vec!["1", "2"]);
+        assert_eq!(db.get(TASKS_KEY).unwrap(), None);
+        assert_eq!(read_version(&db).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_migrates_empty_storage() {
+        let db = temporary_db();
+
+        migrate(&db, MIGRATIONS).unwrap();
+
+        assert_eq!(read_version(&db).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_fails_on_unreadable_task_list() {
+        let db = temporary_db();
+        db.insert(TASKS_KEY, "not json").unwrap();
+
+        assert!(matches!(
+            migrate(&db, MIGRATIONS),
+            Err(StorageError::InvalidTaskList(_))
+        ));
+        assert_eq!(read_version(&db).unwrap(), 0);
+        assert!(db.get(TASKS_KEY).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_fails_on_newer_version() {
+        let db = temporary_db();
+        write_version(&db, 2).unwrap();
+
+        assert!(matches!(
+            migrate(&db, MIGRATIONS),
+            Err(StorageError::UnsupportedSchemaVersion {
+                version: 2,
+                supported: 1
+            })
+        ));
+    }
+
+    static APPLIED: AtomicU32 = AtomicU32::new(0);
+
+    fn record(version: u32) -> StorageResult<()> {
+        // Migrations must run in order.
+        assert_eq!(APPLIED.swap(version, Ordering::SeqCst), version - 1);
+        Ok(())
+    }
+
+    fn fail(_: &sled::Db) -> StorageResult<()> {
+        Err(StorageError::InvalidSchemaVersion)
+    }
+
+    #[test]
+    fn test_runs_pending_migrations_in_order() {
+        let migrations = [
+            Migration {
+                version: 1,
+                description: "first",
+                migrate: |_| record(1),
+            },
+            Migration {
+                version: 2,
+                description: "second",
+                migrate: |_| record(2),
+            },
+            Migration {
+                version: 3,
+                description: "third",
+                migrate: fail,
+            },
+        ];
+        let db = temporary_db();
+
+        assert!(migrate(&db, &migrations[..2]).is_ok());
+        assert_eq!(APPLIED.load(Ordering::SeqCst), 2);
+        assert_eq!(read_version(&db).unwrap(), 2);
+
+        // Applied migrations aren't run again, failed one keeps the version.
+        assert!(migrate(&db, &migrations).is_err());
+        assert_eq!(read_version(&db).unwrap(), 2);
+    }
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+
+use torrent_bot_clients::toloka::TolokaClient;
+
+use crate::task_db::Task;
+
+/// What happened to the topic.
+///
+/// The digest has a section for added, updated, downloaded and deleted topics each,
+/// and groups the other kinds under one section.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum NotificationKind {
+    Added,
+    Updated,
+    Downloaded,
+    Deleted,
+    /// Topic isn't watched anymore, and its files are deleted after the grace period.
+    PendingDeletion,
+    /// Topic pending deletion is watched again.
+    Restored,
+    /// New version of a track-only topic.
+    Tracked,
+    /// Some files of the torrent are left out by file selection rules.
+    FilesSkipped,
+    /// Torrent doesn't fit into the download directory, the topic is retried later.
+    NotEnoughSpace,
+    /// Topic failed too many times, and isn't retried anymore.
+    Failed,
+    /// Topic isn't deleted, as the sync would delete too many of them.
+    DeletionBlocked,
+}
+
+/// Notification about a topic. The server decides how to render and where to send it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Notification {
+    pub(crate) kind: NotificationKind,
+    pub(crate) topic_id: String,
+    pub(crate) title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) category: Option<String>,
+    /// Size of the torrent in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) size: Option<u64>,
+    /// Topic page on toloka.
+    pub(crate) link: String,
+    /// What changed in an updated topic, e.g. which episodes were added.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) summary: Option<String>,
+    /// Poster image of the topic, sent as a photo with the notification.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) poster_url: Option<String>,
+    /// Files left out of the download.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) skipped_files: Vec<String>,
+    /// Bytes the torrent needs in the download directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) required_space: Option<u64>,
+    /// Bytes available in the download directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) available_space: Option<u64>,
+    /// Failed attempts of a failed topic.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) attempts: Option<u32>,
+    /// Last error of a failed topic.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+    /// Hours until files of a topic pending deletion are deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) deleted_in_hours: Option<i64>,
+}
+
+impl Notification {
+    pub(crate) fn new(kind: NotificationKind, topic_id: &str, title: &str) -> Self {
+        Self {
+            kind,
+            topic_id: topic_id.to_string(),
+            title: title.to_string(),
+            category: None,
+            size: None,
+            link: TolokaClient::topic_url(topic_id),
+            summary: None,
+            poster_url: None,
+            skipped_files: vec![],
+            required_space: None,
+            available_space: None,
+            attempts: None,
+            error: None,
+            deleted_in_hours: None,
+        }
+    }
+
+    /// Notification about the topic of the task, with the details the task knows.
+    pub(crate) fn of_task(kind: NotificationKind, task: &Task) -> Self {
+        Self {
+            category: task.category.clone(),
+            size: task.size,
+            poster_url: task.poster_url.clone(),
+            ..Self::new(kind, &task.topic_id, &task.topic_title)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serializes_notification() {
+        let notification = Notification {
+            summary: Some("episodes 5-6 added".to_string()),
+            size: Some(1024),
+            ..Notification::new(NotificationKind::Updated, "t679577", "Тед Лассо")
+        };
+
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({
+                "kind": "updated",
+                "topic_id": "t679577",
+                "title": "Тед Лассо",
+                "size": 1024,
+                "link": "https://toloka.to/t679577",
+                "summary": "episodes 5-6 added",
+            })
+        );
+    }
+
+    #[test]
+    fn test_serializes_failed_topic() {
+        let notification = Notification {
+            attempts: Some(5),
+            error: Some("Torrent was not added in time".to_string()),
+            ..Notification::new(NotificationKind::Failed, "t1", "Дюна")
+        };
+
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({
+                "kind": "failed",
+                "topic_id": "t1",
+                "title": "Дюна",
+                "link": "https://toloka.to/t1",
+                "attempts": 5,
+                "error": "Torrent was not added in time",
+            })
+        );
+    }
+}
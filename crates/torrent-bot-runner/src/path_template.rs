@@ -0,0 +1,259 @@
+use serde::Deserialize;
+
+use torrent_bot_clients::toloka::types::Category;
+
+use crate::sync_plan::PlannedTopic;
+use crate::topic_title::{parse_season, parse_year, short_title};
+
+/// Longest path component produced from a title, in characters.
+const MAX_COMPONENT_LENGTH: usize = 100;
+
+const PLACEHOLDERS: [&str; 5] = ["topic_id", "category", "title", "year", "season"];
+
+/// Templates of download paths, relative to the download client's directory.
+///
+/// Supported placeholders: `{topic_id}`, `{category}`, `{title}`, `{year}` and `{season}`.
+/// A path component with a placeholder that has no value for the topic
+/// (e.g. `Season {season}` for a movie) is left out.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PathTemplates {
+    #[serde(default = "default_path_template", rename = "movies_path_template")]
+    pub movies: String,
+    #[serde(default = "default_path_template", rename = "series_path_template")]
+    pub series: String,
+    #[serde(default = "default_path_template", rename = "other_path_template")]
+    pub other: String,
+}
+
+fn default_path_template() -> String {
+    "{category}".to_string()
+}
+
+impl Default for PathTemplates {
+    fn default() -> Self {
+        Self {
+            movies: default_path_template(),
+            series: default_path_template(),
+            other: default_path_template(),
+        }
+    }
+}
+
+impl PathTemplates {
+    pub fn from_env() -> Result<Self, envy::Error> {
+        let templates: Self = envy::from_env()?;
+        templates.validate().map_err(envy::Error::Custom)?;
+
+        Ok(templates)
+    }
+
+    /// Checks that every placeholder is closed and supported.
+    fn validate(&self) -> Result<(), String> {
+        for template in [&self.movies, &self.series, &self.other] {
+            let mut rest = template.as_str();
+
+            while let Some(start) = rest.find('{') {
+                let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+                    return Err(format!(
+                        "Unclosed placeholder in path template: {}",
+                        template
+                    ));
+                };
+
+                let name = &rest[start + 1..end];
+                if !PLACEHOLDERS.contains(&name) {
+                    return Err(format!(
+                        "Unknown placeholder {{{}}} in path template: {}",
+                        name, template
+                    ));
+                }
+
+                rest = &rest[end + 1..];
+            }
+        }
+
+        Ok(())
+    }
+
+    fn for_category(&self, category: &Category) -> &str {
+        match category {
+            Category::Movies => &self.movies,
+            Category::Series => &self.series,
+            Category::Other(_) => &self.other,
+        }
+    }
+
+    /// Download path of the topic, `None` if nothing is left of it,
+    /// as the topic would be downloaded right into the download directory.
+    pub(crate) fn render(&self, topic: &PlannedTopic) -> Option<String> {
+        let title = short_title(&topic.title);
+        let year = parse_year(&topic.title);
+        let season = parse_season(&topic.title);
+
+        let value = |name: &str| -> Option<String> {
+            match name {
+                "topic_id" => Some(topic.topic_id.clone()),
+                "category" => Some(topic.category.to_string()),
+                "title" => Some(title.clone()),
+                "year" => year.map(|year| year.to_string()),
+                "season" => season.map(|season| season.to_string()),
+                _ => None,
+            }
+        };
+
+        let components = self
+            .for_category(&topic.category)
+            .split('/')
+            .filter_map(|component| render_component(component, &value))
+            .filter(|component| !component.is_empty())
+            .collect::<Vec<_>>();
+
+        match components.is_empty() {
+            true => None,
+            false => Some(components.join("/")),
+        }
+    }
+}
+
+/// Replaces placeholders in a single path component.
+/// Returns `None` if any of them has no value.
+fn render_component(component: &str, value: &dyn Fn(&str) -> Option<String>) -> Option<String> {
+    let mut rendered = String::new();
+    let mut rest = component;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+
+        rendered.push_str(&rest[..start]);
+        rendered.push_str(&sanitize(&value(&rest[start + 1..end])?));
+        rest = &rest[end + 1..];
+    }
+
+    rendered.push_str(rest);
+
+    Some(sanitize(&rendered))
+}
+
+/// Makes the value safe to use as a single path component: characters reserved on
+/// common file systems are replaced, and leading dots are trimmed, so `..` can't appear.
+/// Letters of any script, including Cyrillic, are kept as they are.
+fn sanitize(value: &str) -> String {
+    let replaced = value
+        .chars()
+        .map(|char| match char {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => ' ',
+            char if char.is_control() => ' ',
+            char => char,
+        })
+        .collect::<String>();
+
+    let collapsed = replaced.split_whitespace().collect::<Vec<_>>().join(" ");
+    let truncated = collapsed
+        .chars()
+        .take(MAX_COMPONENT_LENGTH)
+        .collect::<String>();
+
+    truncated
+        .trim_start_matches(['.', ' '])
+        .trim_end_matches(['.', ' '])
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topic(title: &str, category: Category) -> PlannedTopic {
+        PlannedTopic {
+            topic_id: "t679577".to_string(),
+            title: title.to_string(),
+            category,
+            download_id: "1".to_string(),
+            registered_at: "2024-01-01".to_string(),
+            directory: None,
+            poster_url: None,
+        }
+    }
+
+    fn templates(series: &str) -> PathTemplates {
+        PathTemplates {
+            series: series.to_string(),
+            ..PathTemplates::default()
+        }
+    }
+
+    #[test]
+    fn test_default_template_is_category() {
+        let topic = topic("Дюна (2021)", Category::Movies);
+
+        assert_eq!(
+            PathTemplates::default().render(&topic).as_deref(),
+            Some("Movies")
+        );
+    }
+
+    #[test]
+    fn test_renders_series_template() {
+        let topic = topic(
+            "Тед Лассо / Ted Lasso (Сезон 2, серії 1-12 з 12) (2021) WEB-DL 1080p",
+            Category::Series,
+        );
+
+        assert_eq!(
+            templates("Series/{title}/Season {season}")
+                .render(&topic)
+                .as_deref(),
+            Some("Series/Тед Лассо/Season 2")
+        );
+        assert_eq!(
+            templates("{category}/{title} ({year})/{topic_id}")
+                .render(&topic)
+                .as_deref(),
+            Some("Series/Тед Лассо (2021)/t679577")
+        );
+    }
+
+    #[test]
+    fn test_skips_components_without_value() {
+        let topic = topic("Тед Лассо / Ted Lasso (2021)", Category::Series);
+
+        assert_eq!(
+            templates("Series/{title}/Season {season}")
+                .render(&topic)
+                .as_deref(),
+            Some("Series/Тед Лассо")
+        );
+    }
+
+    #[test]
+    fn test_sanitizes_values() {
+        let topic = topic("../Хто: я? <Who*Am|I> / Who Am I", Category::Series);
+
+        assert_eq!(
+            templates("{title}").render(&topic).as_deref(),
+            Some("Хто я Who Am I")
+        );
+        assert_eq!(sanitize(" ..Ґаздиня ї'є. "), "Ґаздиня ї'є");
+        assert_eq!(sanitize("\u{0}.."), "");
+    }
+
+    #[test]
+    fn test_empty_path_is_not_rendered() {
+        let topic = topic("..", Category::Series);
+
+        assert_eq!(templates("{title}/Season {season}").render(&topic), None);
+    }
+
+    #[test]
+    fn test_validates_placeholders() {
+        assert!(
+            templates("{category}/{title} ({year})/Season {season}/{topic_id}")
+                .validate()
+                .is_ok()
+        );
+        assert!(templates("{category}/{name}").validate().is_err());
+        assert!(templates("{category}/{title").validate().is_err());
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use torrent_bot_clients::download_client::{
+    AddedTorrent, DownloadClient, DownloadClientResult, RemoveStrategy, TorrentFile, TorrentId,
+    TorrentStatus,
+};
+
+use crate::sync_plan::SyncAction;
+
+/// Result of a single applied action.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct TopicResult {
+    pub(crate) topic_id: String,
+    pub(crate) title: String,
+    pub(crate) action: String,
+    /// `None` if the action succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+/// What a single sync run did, stored after every run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SyncReport {
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) finished_at: DateTime<Utc>,
+    /// Number of planned actions by kind.
+    pub(crate) action_counts: BTreeMap<String, usize>,
+    pub(crate) blocked_deletions: usize,
+    /// Topics whose deletion was blocked, so the same blocked plan is reported once.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) blocked_topic_ids: Vec<String>,
+    pub(crate) topics: Vec<TopicResult>,
+    pub(crate) toloka_time_ms: u64,
+    pub(crate) download_client_time_ms: u64,
+    /// The run stopped early on shutdown.
+    pub(crate) interrupted: bool,
+    /// Error that stopped the whole run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+impl SyncReport {
+    pub(crate) fn start() -> Self {
+        let now = Utc::now();
+
+        Self {
+            started_at: now,
+            finished_at: now,
+            action_counts: BTreeMap::new(),
+            blocked_deletions: 0,
+            blocked_topic_ids: vec![],
+            topics: vec![],
+            toloka_time_ms: 0,
+            download_client_time_ms: 0,
+            interrupted: false,
+            error: None,
+        }
+    }
+
+    pub(crate) fn count_actions(&mut self, actions: &[SyncAction]) {
+        for action in actions {
+            *self
+                .action_counts
+                .entry(action.kind().to_string())
+                .or_default() += 1;
+        }
+    }
+
+    pub(crate) fn add_result(&mut self, action: &SyncAction, error: Option<String>) {
+        self.topics.push(TopicResult {
+            topic_id: action.topic_id().to_string(),
+            title: action.title().to_string(),
+            action: action.kind().to_string(),
+            error,
+        });
+    }
+
+    /// Whether the run certainly got past planning, so its blocked deletions are known.
+    pub(crate) fn is_planned(&self) -> bool {
+        self.blocked_deletions > 0 || (self.error.is_none() && !self.interrupted)
+    }
+
+    pub(crate) fn failed(&self) -> impl Iterator<Item = &TopicResult> {
+        self.topics.iter().filter(|topic| topic.error.is_some())
+    }
+
+    pub(crate) fn finish(&mut self, timings: &Timings) {
+        self.finished_at = Utc::now();
+        self.toloka_time_ms = timings.toloka.load(Ordering::Relaxed) / 1000;
+        self.download_client_time_ms = timings.download_client.load(Ordering::Relaxed) / 1000;
+    }
+}
+
+/// Time spent waiting for toloka and the download client during a run, in microseconds.
+#[derive(Debug, Default)]
+pub(crate) struct Timings {
+    toloka: AtomicU64,
+    download_client: AtomicU64,
+}
+
+impl Timings {
+    /// Runs a toloka request, counting the time it takes.
+    pub(crate) async fn toloka<T>(&self, future: impl Future<Output = T>) -> T {
+        measure(&self.toloka, future).await
+    }
+}
+
+async fn measure<T>(counter: &AtomicU64, future: impl Future<Output = T>) -> T {
+    let started = Instant::now();
+    let output = future.await;
+    let elapsed = u64::try_from(started.elapsed().as_micros()).unwrap_or(u64::MAX);
+    counter.fetch_add(elapsed, Ordering::Relaxed);
+
+    output
+}
+
+/// Download client that counts the time spent in every request.
+pub(crate) struct TimedDownloadClient<'a> {
+    inner: &'a dyn DownloadClient,
+    timings: &'a Timings,
+}
+
+impl<'a> TimedDownloadClient<'a> {
+    pub(crate) fn new(inner: &'a dyn DownloadClient, timings: &'a Timings) -> Self {
+        Self { inner, timings }
+    }
+}
+
+#[async_trait::async_trait]
+impl DownloadClient for TimedDownloadClient<'_> {
+    async fn add(
+        &self,
+        torrent_file_content: Vec<u8>,
+        path: &str,
+    ) -> DownloadClientResult<AddedTorrent> {
+        measure(
+            &self.timings.download_client,
+            self.inner.add(torrent_file_content, path),
+        )
+        .await
+    }
+
+    async fn start(&self, torrent_id: &TorrentId) -> DownloadClientResult<()> {
+        measure(&self.timings.download_client, self.inner.start(torrent_id)).await
+    }
+
+    async fn remove(
+        &self,
+        torrent_id: &TorrentId,
+        remove_strategy: RemoveStrategy,
+    ) -> DownloadClientResult<()> {
+        measure(
+            &self.timings.download_client,
+            self.inner.remove(torrent_id, remove_strategy),
+        )
+        .await
+    }
+
+    async fn get_status(
+        &self,
+        torrent_id: &TorrentId,
+    ) -> DownloadClientResult<Option<TorrentStatus>> {
+        measure(
+            &self.timings.download_client,
+            self.inner.get_status(torrent_id),
+        )
+        .await
+    }
+
+    async fn set_location(&self, torrent_id: &TorrentId, path: &str) -> DownloadClientResult<()> {
+        measure(
+            &self.timings.download_client,
+            self.inner.set_location(torrent_id, path),
+        )
+        .await
+    }
+
+    async fn set_labels(
+        &self,
+        torrent_id: &TorrentId,
+        labels: Vec<String>,
+    ) -> DownloadClientResult<()> {
+        measure(
+            &self.timings.download_client,
+            self.inner.set_labels(torrent_id, labels),
+        )
+        .await
+    }
+
+    async fn get_files(&self, torrent_id: &TorrentId) -> DownloadClientResult<Vec<TorrentFile>> {
+        measure(
+            &self.timings.download_client,
+            self.inner.get_files(torrent_id),
+        )
+        .await
+    }
+
+    async fn rename_path(
+        &self,
+        torrent_id: &TorrentId,
+        path: &str,
+        name: &str,
+    ) -> DownloadClientResult<()> {
+        measure(
+            &self.timings.download_client,
+            self.inner.rename_path(torrent_id, path, name),
+        )
+        .await
+    }
+
+    async fn verify(&self, torrent_id: &TorrentId) -> DownloadClientResult<()> {
+        measure(&self.timings.download_client, self.inner.verify(torrent_id)).await
+    }
+
+    async fn set_files_wanted(
+        &self,
+        torrent_id: &TorrentId,
+        files_wanted: Vec<usize>,
+        files_unwanted: Vec<usize>,
+    ) -> DownloadClientResult<()> {
+        measure(
+            &self.timings.download_client,
+            self.inner
+                .set_files_wanted(torrent_id, files_wanted, files_unwanted),
+        )
+        .await
+    }
+
+    async fn get_free_space(&self) -> DownloadClientResult<u64> {
+        measure(&self.timings.download_client, self.inner.get_free_space()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sync_plan::PlannedTopic;
+    use torrent_bot_clients::toloka::types::Category;
+
+    fn add(topic_id: &str) -> SyncAction {
+        SyncAction::Add {
+            topic: PlannedTopic {
+                topic_id: topic_id.to_string(),
+                title: format!("Topic {}", topic_id),
+                category: Category::Series,
+                download_id: "1".to_string(),
+                registered_at: "2024-01-01".to_string(),
+                directory: None,
+                poster_url: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_report_counts_actions_and_results() {
+        let mut report = SyncReport::start();
+        let actions = vec![
+            add("1"),
+            add("2"),
+            SyncAction::MarkFinished {
+                topic_id: "3".to_string(),
+                title: "Topic 3".to_string(),
+            },
+        ];
+
+        report.count_actions(&actions);
+        report.add_result(&actions[0], None);
+        report.add_result(&actions[1], Some("404".to_string()));
+        report.finish(&Timings::default());
+
+        assert_eq!(report.action_counts.get("add"), Some(&2));
+        assert_eq!(report.action_counts.get("mark_finished"), Some(&1));
+        assert_eq!(report.topics.len(), 2);
+        assert_eq!(
+            report
+                .failed()
+                .map(|topic| topic.topic_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["2"]
+        );
+        assert!(report.finished_at >= report.started_at);
+    }
+
+    #[test]
+    fn test_report_is_planned_unless_it_failed_before_planning() {
+        let report = SyncReport::start();
+        assert!(report.is_planned());
+
+        let report = SyncReport {
+            error: Some("Invalid login or password".to_string()),
+            ..SyncReport::start()
+        };
+        assert!(!report.is_planned());
+
+        let report = SyncReport {
+            blocked_deletions: 2,
+            error: Some("Invalid login or password".to_string()),
+            ..SyncReport::start()
+        };
+        assert!(report.is_planned());
+    }
+
+    #[actix_rt::test]
+    async fn test_timings_count_toloka_time() {
+        let timings = Timings::default();
+
+        let value = timings
+            .toloka(async {
+                actix_rt::time::sleep(std::time::Duration::from_millis(5)).await;
+                42
+            })
+            .await;
+
+        let mut report = SyncReport::start();
+        report.finish(&timings);
+
+        assert_eq!(value, 42);
+        assert!(report.toloka_time_ms >= 5);
+        assert_eq!(report.download_client_time_ms, 0);
+    }
+}
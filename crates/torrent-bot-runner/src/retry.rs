@@ -0,0 +1,122 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::task_db::{Task, TaskStatus};
+
+/// Longest delay between attempts, however many times the topic failed.
+const MAX_BACKOFF_HOURS: i64 = 24;
+
+/// How failed topics are retried: the delay doubles after every failed attempt,
+/// and after `max_attempts` failures the topic isn't retried anymore. Permanently failed
+/// topics can still be deleted once they aren't watched, which is retried with the same delay.
+#[derive(Debug, Clone)]
+pub(crate) struct RetryPolicy {
+    pub(crate) max_attempts: u32,
+    pub(crate) backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay after the given number of failed attempts.
+    pub(crate) fn delay(&self, attempts: u32) -> Duration {
+        let factor = 2_i32.saturating_pow(attempts.saturating_sub(1));
+
+        self.backoff
+            .checked_mul(factor)
+            .unwrap_or(Duration::MAX)
+            .min(Duration::hours(MAX_BACKOFF_HOURS))
+    }
+
+    /// Counts failed attempt on the task, and schedules the next one.
+    /// Returns `true` if the task just became permanently failed.
+    pub(crate) fn record_failure(&self, task: &mut Task, error: &str, now: DateTime<Utc>) -> bool {
+        task.attempts += 1;
+        task.last_error = Some(error.to_string());
+
+        task.next_retry_at = Some(now + self.delay(task.attempts));
+
+        if task.attempts < self.max_attempts {
+            return false;
+        }
+
+        let was_failed = matches!(task.task_status, TaskStatus::PermanentlyFailed);
+        task.task_status = TaskStatus::PermanentlyFailed;
+
+        !was_failed
+    }
+}
+
+/// Whether the task is waiting for its next attempt after a failure.
+pub(crate) fn is_backing_off(task: &Task, now: DateTime<Utc>) -> bool {
+    task.next_retry_at
+        .is_some_and(|next_retry_at| next_retry_at > now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::task_db::TaskOverrides;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::minutes(15),
+        }
+    }
+
+    fn task() -> Task {
+        Task {
+            topic_id: "1".to_string(),
+            topic_title: "Topic 1".to_string(),
+            previous_title: None,
+            topic_download_registered_at: "2024-01-01".to_string(),
+            transmission_torrent_id: None,
+            torrent_hash: None,
+            category: None,
+            size: None,
+            poster_url: None,
+            task_status: TaskStatus::Added,
+            last_error: None,
+            attempts: 0,
+            next_retry_at: None,
+            pending_deletion_since: None,
+            overrides: TaskOverrides::default(),
+        }
+    }
+
+    #[test]
+    fn test_delay_doubles_up_to_limit() {
+        let policy = policy();
+
+        assert_eq!(policy.delay(1), Duration::minutes(15));
+        assert_eq!(policy.delay(2), Duration::minutes(30));
+        assert_eq!(policy.delay(3), Duration::minutes(60));
+        assert_eq!(policy.delay(10), Duration::hours(MAX_BACKOFF_HOURS));
+        assert_eq!(policy.delay(u32::MAX), Duration::hours(MAX_BACKOFF_HOURS));
+    }
+
+    #[test]
+    fn test_failures_are_counted_until_permanent() {
+        let policy = policy();
+        let now = Utc::now();
+        let mut task = task();
+
+        assert!(!policy.record_failure(&mut task, "404", now));
+        assert_eq!(task.attempts, 1);
+        assert_eq!(task.last_error.as_deref(), Some("404"));
+        assert_eq!(task.next_retry_at, Some(now + Duration::minutes(15)));
+        assert!(is_backing_off(&task, now));
+        assert!(!is_backing_off(&task, now + Duration::minutes(15)));
+
+        assert!(!policy.record_failure(&mut task, "404", now));
+        assert_eq!(task.next_retry_at, Some(now + Duration::minutes(30)));
+
+        assert!(policy.record_failure(&mut task, "500", now));
+        assert_eq!(task.attempts, 3);
+        assert_eq!(task.last_error.as_deref(), Some("500"));
+        assert_eq!(task.next_retry_at, Some(now + Duration::minutes(60)));
+        assert!(matches!(task.task_status, TaskStatus::PermanentlyFailed));
+
+        // Becoming permanently failed is reported only once.
+        assert!(!policy.record_failure(&mut task, "500", now));
+    }
+}
@@ -0,0 +1,277 @@
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use tracing::warn;
+
+use crate::event_log::{Event, EventFilter};
+use crate::migrations::{migrate, MIGRATIONS};
+use crate::report::SyncReport;
+use crate::task_db::{StorageError, StorageResult, Task, TaskDb, MAX_REPORTS};
+
+pub(crate) const TASKS_TREE: &str = "tasks";
+const REPORTS_TREE: &str = "sync_reports";
+const EVENTS_TREE: &str = "events";
+
+/// Storage in a sled database, with a tree per kind of data.
+pub(crate) struct SledTaskDb {
+    db: sled::Db,
+    /// Tasks keyed by topic id.
+    tasks: sled::Tree,
+}
+
+fn serialize(task: &Task) -> Vec<u8> {
+    serde_json::to_vec(task).unwrap()
+}
+
+fn deserialize(topic_id: &[u8], raw: &[u8]) -> StorageResult<Task> {
+    serde_json::from_slice(raw).map_err(|source| StorageError::InvalidTask {
+        topic_id: String::from_utf8_lossy(topic_id).to_string(),
+        source,
+    })
+}
+
+fn transaction_error(error: TransactionError<StorageError>) -> StorageError {
+    match error {
+        TransactionError::Storage(error) => error.into(),
+        TransactionError::Abort(error) => error,
+    }
+}
+
+impl SledTaskDb {
+    /// Fails with [`StorageError::Locked`] while another process has the storage open,
+    /// as sled allows a single process only.
+    pub(crate) fn create(path: &str) -> StorageResult<Self> {
+        let db = sled::open(path).map_err(|error| match error {
+            sled::Error::Io(io_error)
+                if io_error.to_string().contains("could not acquire lock") =>
+            {
+                StorageError::Locked(path.to_string())
+            }
+            error => error.into(),
+        })?;
+
+        Self::open(db)
+    }
+
+    /// Brings the storage to the latest schema version.
+    pub(crate) fn open(db: sled::Db) -> StorageResult<Self> {
+        migrate(&db, MIGRATIONS)?;
+
+        let tasks = db.open_tree(TASKS_TREE)?;
+
+        Ok(Self { db, tasks })
+    }
+}
+
+impl TaskDb for SledTaskDb {
+    #[tracing::instrument(err, skip(self))]
+    fn get_tasks(&self) -> StorageResult<Vec<Task>> {
+        let mut tasks = vec![];
+
+        for entry in self.tasks.iter() {
+            let (key, raw) = entry?;
+            tasks.push(deserialize(&key, &raw)?);
+        }
+
+        Ok(tasks)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn get_invalid_topic_ids(&self) -> StorageResult<Vec<String>> {
+        let mut topic_ids = vec![];
+
+        for entry in self.tasks.iter() {
+            let (key, raw) = entry?;
+
+            if deserialize(&key, &raw).is_err() {
+                topic_ids.push(String::from_utf8_lossy(&key).to_string());
+            }
+        }
+
+        Ok(topic_ids)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn get_task_by_topic_id(&self, topic_id: &str) -> StorageResult<Option<Task>> {
+        let task = self
+            .tasks
+            .get(topic_id)?
+            .map(|raw| deserialize(topic_id.as_bytes(), &raw))
+            .transpose()?;
+
+        Ok(task)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn replace_task(&self, task: Task) -> StorageResult<()> {
+        self.tasks
+            .insert(task.topic_id.as_bytes(), serialize(&task))?;
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn delete_task_by_topic_id(&self, topic_id: &str) -> StorageResult<()> {
+        self.tasks.remove(topic_id)?;
+        Ok(())
+    }
+
+    fn update_task(&self, topic_id: &str, update: &dyn Fn(&mut Task)) -> StorageResult<()> {
+        self.tasks
+            .transaction(|tree| {
+                let Some(raw) = tree.get(topic_id)? else {
+                    return Ok(());
+                };
+                let mut task = deserialize(topic_id.as_bytes(), &raw)
+                    .map_err(ConflictableTransactionError::Abort)?;
+
+                update(&mut task);
+                tree.insert(topic_id.as_bytes(), serialize(&task))?;
+
+                Ok(())
+            })
+            .map_err(transaction_error)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn save_tasks(&self, tasks: &[Task]) -> StorageResult<()> {
+        let mut batch = sled::Batch::default();
+
+        for key in self.tasks.iter().keys() {
+            batch.remove(key?);
+        }
+        for task in tasks.iter() {
+            batch.insert(task.topic_id.as_bytes(), serialize(task));
+        }
+
+        self.tasks.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// Reports are keyed by their start time.
+    #[tracing::instrument(err, skip(self, report))]
+    fn save_report(&self, report: &SyncReport) -> StorageResult<()> {
+        let reports = self.db.open_tree(REPORTS_TREE)?;
+        let key = report.started_at.timestamp_micros().to_be_bytes();
+        let vec = serde_json::to_vec(report).unwrap();
+        let _ = reports.insert(key, vec)?;
+
+        while reports.len() > MAX_REPORTS {
+            reports.pop_min()?;
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn get_reports(&self, limit: usize) -> StorageResult<Vec<SyncReport>> {
+        let reports = self.db.open_tree(REPORTS_TREE)?;
+        let mut result = vec![];
+
+        for entry in reports.iter().rev().take(limit) {
+            let (_, raw) = entry?;
+
+            match serde_json::from_slice(raw.as_ref()) {
+                Ok(report) => result.push(report),
+                Err(error) => warn!(?error, "Unable to read sync report. Skipping..."),
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Events are keyed by their time, so they are read in order.
+    #[tracing::instrument(err, skip(self))]
+    fn append_event(&self, event: &Event) -> StorageResult<()> {
+        let events = self.db.open_tree(EVENTS_TREE)?;
+        // Unique id keeps events that happen in the same microsecond apart.
+        let mut key = event.at.timestamp_micros().to_be_bytes().to_vec();
+        key.extend_from_slice(&self.db.generate_id()?.to_be_bytes());
+
+        events.insert(key, serde_json::to_vec(event).unwrap())?;
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn get_events(&self, filter: &EventFilter) -> StorageResult<Vec<Event>> {
+        let events = self.db.open_tree(EVENTS_TREE)?;
+        // Keys start with the time, so only the requested range is read.
+        let since = filter
+            .since
+            .map_or(0, |since| since.timestamp_micros().max(0));
+        let until = filter
+            .until
+            .map_or(i64::MAX, |until| until.timestamp_micros().saturating_add(1));
+        let mut result = vec![];
+
+        for entry in events.range(since.to_be_bytes()..until.to_be_bytes()) {
+            let (_, raw) = entry?;
+
+            match serde_json::from_slice::<Event>(raw.as_ref()) {
+                Ok(event) if filter.matches(&event) => result.push(event),
+                Ok(_) => {}
+                Err(error) => warn!(?error, "Unable to read event. Skipping..."),
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn flush(&self) -> StorageResult<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::task_db::test_suite::{task, topic_ids};
+
+    fn temporary_db() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    crate::task_db::task_db_tests!(SledTaskDb::open(temporary_db()).unwrap());
+
+    #[test]
+    fn test_fails_when_locked() {
+        let path = std::env::temp_dir().join(format!("torrent-bot-lock-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let task_db = SledTaskDb::create(path).unwrap();
+        let result = SledTaskDb::create(path);
+        drop(task_db);
+        std::fs::remove_dir_all(path).unwrap();
+
+        assert!(matches!(result, Err(StorageError::Locked(locked)) if locked == path));
+    }
+
+    #[test]
+    fn test_migrates_tasks_key() {
+        let db = temporary_db();
+        db.insert(
+            "torrent_bot_tasks",
+            serde_json::to_vec(&vec![task("1"), task("2")]).unwrap(),
+        )
+        .unwrap();
+
+        let task_db = SledTaskDb::open(db).unwrap();
+
+        assert_eq!(topic_ids(&task_db), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn test_fails_on_unreadable_task() {
+        let task_db = SledTaskDb::open(temporary_db()).unwrap();
+        task_db.replace_task(task("1")).unwrap();
+        task_db.tasks.insert("2", r#"{"topic_id": 2}"#).unwrap();
+
+        assert!(matches!(
+            task_db.get_tasks(),
+            Err(StorageError::InvalidTask { topic_id, .. }) if topic_id == "2"
+        ));
+        assert!(task_db.get_task_by_topic_id("2").is_err());
+        assert!(task_db.mark_task_as_finished_by_topic_id("2").is_err());
+        assert_eq!(task_db.get_invalid_topic_ids().unwrap(), vec!["2"]);
+        assert!(SledTaskDb::open(task_db.db.clone()).is_ok());
+    }
+}
@@ -0,0 +1,374 @@
+use std::sync::{Mutex, MutexGuard};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::{info, warn};
+
+use crate::event_log::{Event, EventFilter};
+use crate::report::SyncReport;
+use crate::task_db::{StorageError, StorageResult, Task, TaskDb, MAX_REPORTS};
+
+/// Schema changes, in order. `user_version` of the database is the number of applied ones.
+/// Rows keep their data as JSON, so it can be read with SQLite's JSON functions.
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE tasks (
+        topic_id TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE sync_reports (
+        started_at INTEGER PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE events (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        at INTEGER NOT NULL,
+        topic_id TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX events_at ON events (at);
+    CREATE INDEX events_topic_id ON events (topic_id, at);
+"];
+
+/// Storage in a SQLite database.
+pub(crate) struct SqliteTaskDb {
+    connection: Mutex<Connection>,
+}
+
+fn serialize<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+fn deserialize(topic_id: &str, raw: &str) -> StorageResult<Task> {
+    serde_json::from_str(raw).map_err(|source| StorageError::InvalidTask {
+        topic_id: topic_id.to_string(),
+        source,
+    })
+}
+
+impl SqliteTaskDb {
+    pub(crate) fn create(path: &str) -> StorageResult<Self> {
+        Self::open(Connection::open(path)?)
+    }
+
+    /// Brings the schema to the latest version.
+    fn open(mut connection: Connection) -> StorageResult<Self> {
+        migrate(&mut connection)?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap()
+    }
+}
+
+/// Applies pending migrations, each one in its own transaction together with the version.
+fn migrate(connection: &mut Connection) -> StorageResult<()> {
+    let version: u32 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let supported = MIGRATIONS.len() as u32;
+
+    if version > supported {
+        return Err(StorageError::UnsupportedSchemaVersion { version, supported });
+    }
+
+    for (applied, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", applied + 1)?;
+        transaction.commit()?;
+
+        info!(version = applied + 1, "Migrated storage");
+    }
+
+    Ok(())
+}
+
+impl TaskDb for SqliteTaskDb {
+    #[tracing::instrument(err, skip(self))]
+    fn get_tasks(&self) -> StorageResult<Vec<Task>> {
+        let connection = self.connection();
+        let mut statement =
+            connection.prepare("SELECT topic_id, data FROM tasks ORDER BY topic_id")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut tasks = vec![];
+        for row in rows {
+            let (topic_id, raw) = row?;
+            tasks.push(deserialize(&topic_id, &raw)?);
+        }
+
+        Ok(tasks)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn get_invalid_topic_ids(&self) -> StorageResult<Vec<String>> {
+        let connection = self.connection();
+        let mut statement =
+            connection.prepare("SELECT topic_id, data FROM tasks ORDER BY topic_id")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut topic_ids = vec![];
+        for row in rows {
+            let (topic_id, raw) = row?;
+
+            if deserialize(&topic_id, &raw).is_err() {
+                topic_ids.push(topic_id);
+            }
+        }
+
+        Ok(topic_ids)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn get_task_by_topic_id(&self, topic_id: &str) -> StorageResult<Option<Task>> {
+        let raw: Option<String> = self
+            .connection()
+            .query_row(
+                "SELECT data FROM tasks WHERE topic_id = ?1",
+                [topic_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        raw.map(|raw| deserialize(topic_id, &raw)).transpose()
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn replace_task(&self, task: Task) -> StorageResult<()> {
+        self.connection().execute(
+            "INSERT OR REPLACE INTO tasks (topic_id, data) VALUES (?1, ?2)",
+            params![task.topic_id, serialize(&task)],
+        )?;
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn delete_task_by_topic_id(&self, topic_id: &str) -> StorageResult<()> {
+        self.connection()
+            .execute("DELETE FROM tasks WHERE topic_id = ?1", [topic_id])?;
+        Ok(())
+    }
+
+    fn update_task(&self, topic_id: &str, update: &dyn Fn(&mut Task)) -> StorageResult<()> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+
+        let raw: Option<String> = transaction
+            .query_row(
+                "SELECT data FROM tasks WHERE topic_id = ?1",
+                [topic_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(raw) = raw else {
+            return Ok(());
+        };
+
+        let mut task = deserialize(topic_id, &raw)?;
+        update(&mut task);
+        transaction.execute(
+            "UPDATE tasks SET data = ?2 WHERE topic_id = ?1",
+            params![topic_id, serialize(&task)],
+        )?;
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn save_tasks(&self, tasks: &[Task]) -> StorageResult<()> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+
+        transaction.execute("DELETE FROM tasks", [])?;
+        for task in tasks.iter() {
+            transaction.execute(
+                "INSERT OR REPLACE INTO tasks (topic_id, data) VALUES (?1, ?2)",
+                params![task.topic_id, serialize(task)],
+            )?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self, report))]
+    fn save_report(&self, report: &SyncReport) -> StorageResult<()> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+
+        transaction.execute(
+            "INSERT OR REPLACE INTO sync_reports (started_at, data) VALUES (?1, ?2)",
+            params![report.started_at.timestamp_micros(), serialize(report)],
+        )?;
+        transaction.execute(
+            "DELETE FROM sync_reports WHERE started_at NOT IN
+                (SELECT started_at FROM sync_reports ORDER BY started_at DESC LIMIT ?1)",
+            [MAX_REPORTS as i64],
+        )?;
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn get_reports(&self, limit: usize) -> StorageResult<Vec<SyncReport>> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare("SELECT data FROM sync_reports ORDER BY started_at DESC LIMIT ?1")?;
+        let rows = statement.query_map([i64::try_from(limit).unwrap_or(i64::MAX)], |row| {
+            row.get::<_, String>(0)
+        })?;
+
+        let mut result = vec![];
+        for row in rows {
+            match serde_json::from_str(&row?) {
+                Ok(report) => result.push(report),
+                Err(error) => warn!(?error, "Unable to read sync report. Skipping..."),
+            }
+        }
+
+        Ok(result)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn append_event(&self, event: &Event) -> StorageResult<()> {
+        self.connection().execute(
+            "INSERT INTO events (at, topic_id, data) VALUES (?1, ?2, ?3)",
+            params![
+                event.at.timestamp_micros(),
+                event.topic_id,
+                serialize(event)
+            ],
+        )?;
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn get_events(&self, filter: &EventFilter) -> StorageResult<Vec<Event>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT data FROM events
+                WHERE at >= ?1 AND at <= ?2 AND (?3 IS NULL OR topic_id = ?3)
+                ORDER BY at, id",
+        )?;
+        let rows = statement.query_map(
+            params![
+                filter
+                    .since
+                    .map_or(i64::MIN, |since| since.timestamp_micros()),
+                filter
+                    .until
+                    .map_or(i64::MAX, |until| until.timestamp_micros()),
+                filter.topic_id,
+            ],
+            |row| row.get::<_, String>(0),
+        )?;
+
+        let mut result = vec![];
+        for row in rows {
+            match serde_json::from_str::<Event>(&row?) {
+                Ok(event) if filter.matches(&event) => result.push(event),
+                Ok(_) => {}
+                Err(error) => warn!(?error, "Unable to read event. Skipping..."),
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Changes are written when their statement completes.
+    fn flush(&self) -> StorageResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::StorageBackend;
+    use crate::task_db::test_suite::task;
+    use crate::task_db::{open_task_db, open_task_db_lenient};
+
+    fn in_memory() -> SqliteTaskDb {
+        SqliteTaskDb::open(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    crate::task_db::task_db_tests!(in_memory());
+
+    #[test]
+    fn test_migrates_schema_once() {
+        let mut connection = Connection::open_in_memory().unwrap();
+
+        migrate(&mut connection).unwrap();
+        migrate(&mut connection).unwrap();
+
+        let version: u32 = connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len() as u32);
+    }
+
+    #[test]
+    fn test_fails_on_newer_schema() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+
+        assert!(matches!(
+            SqliteTaskDb::open(connection),
+            Err(StorageError::UnsupportedSchemaVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn test_fails_on_unreadable_task() {
+        let task_db = in_memory();
+        task_db.replace_task(task("1")).unwrap();
+        task_db
+            .connection()
+            .execute(
+                "INSERT INTO tasks (topic_id, data) VALUES ('2', '{\"topic_id\": 2}')",
+                [],
+            )
+            .unwrap();
+
+        assert!(matches!(
+            task_db.get_tasks(),
+            Err(StorageError::InvalidTask { topic_id, .. }) if topic_id == "2"
+        ));
+        assert!(task_db.get_task_by_topic_id("2").is_err());
+        assert!(task_db.mark_task_as_finished_by_topic_id("2").is_err());
+        assert_eq!(task_db.get_invalid_topic_ids().unwrap(), vec!["2"]);
+    }
+
+    #[test]
+    fn test_open_fails_on_unreadable_task() {
+        let path = std::env::temp_dir().join(format!("torrent-bot-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let task_db = SqliteTaskDb::create(path).unwrap();
+        task_db
+            .connection()
+            .execute(
+                "INSERT INTO tasks (topic_id, data) VALUES ('2', '{\"topic_id\": 2}')",
+                [],
+            )
+            .unwrap();
+        drop(task_db);
+
+        let result = open_task_db(&StorageBackend::Sqlite, path);
+        let lenient = open_task_db_lenient(&StorageBackend::Sqlite, path);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(matches!(result, Err(StorageError::UnreadableTasks(ids)) if ids == ["2"]));
+        assert!(lenient.is_ok());
+    }
+}
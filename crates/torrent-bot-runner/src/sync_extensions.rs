@@ -1,20 +1,20 @@
 use crate::task_db;
-use torrent_bot_clients::transmission;
+use torrent_bot_clients::download_client;
 
-impl Into<task_db::TorrentId> for &transmission::TorrentId {
+impl Into<task_db::TorrentId> for &download_client::TorrentId {
     fn into(self) -> task_db::TorrentId {
         match self {
-            transmission::TorrentId::Id(id) => task_db::TorrentId::Id(*id),
-            transmission::TorrentId::Hash(hash) => task_db::TorrentId::Hash(hash.to_string()),
+            download_client::TorrentId::Id(id) => task_db::TorrentId::Id(*id),
+            download_client::TorrentId::Hash(hash) => task_db::TorrentId::Hash(hash.to_string()),
         }
     }
 }
 
-impl Into<transmission::TorrentId> for &task_db::TorrentId {
-    fn into(self) -> transmission::TorrentId {
+impl Into<download_client::TorrentId> for &task_db::TorrentId {
+    fn into(self) -> download_client::TorrentId {
         match self {
-            task_db::TorrentId::Id(id) => transmission::TorrentId::Id(*id),
-            task_db::TorrentId::Hash(hash) => transmission::TorrentId::Hash(hash.to_string()),
+            task_db::TorrentId::Id(id) => download_client::TorrentId::Id(*id),
+            task_db::TorrentId::Hash(hash) => download_client::TorrentId::Hash(hash.to_string()),
         }
     }
 }
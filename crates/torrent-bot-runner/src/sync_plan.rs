@@ -0,0 +1,1280 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Serializer};
+use tracing::debug;
+
+use torrent_bot_clients::download_client::DownloadClient;
+use torrent_bot_clients::toloka;
+use torrent_bot_clients::toloka::types::{Category, Topic, WatchedTopics};
+
+use crate::deletion_guard::DeletionGuard;
+use crate::report::Timings;
+use crate::retry::is_backing_off;
+use crate::sync_v2::SyncError;
+use crate::task_db::{Task, TaskDb, TaskStatus, TorrentId};
+
+fn serialize_category<S: Serializer>(
+    category: &Category,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(category)
+}
+
+/// Watched topic that has to be downloaded.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct PlannedTopic {
+    pub(crate) topic_id: String,
+    pub(crate) title: String,
+    #[serde(serialize_with = "serialize_category")]
+    pub(crate) category: Category,
+    pub(crate) download_id: String,
+    pub(crate) registered_at: String,
+    /// Download path set on the task, used instead of the path template.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) directory: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) poster_url: Option<String>,
+}
+
+impl From<&Topic> for PlannedTopic {
+    fn from(topic: &Topic) -> Self {
+        Self {
+            topic_id: topic.topic_meta.topic_id.clone(),
+            title: topic.topic_meta.title.clone(),
+            category: topic.topic_meta.category.clone(),
+            download_id: topic.download_meta.download_id.clone(),
+            registered_at: topic.download_meta.registered_at.clone(),
+            directory: None,
+            poster_url: topic.download_meta.poster_url.clone(),
+        }
+    }
+}
+
+/// Single change a sync run would make.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub(crate) enum SyncAction {
+    Add {
+        #[serde(flatten)]
+        topic: PlannedTopic,
+    },
+    Update {
+        #[serde(flatten)]
+        topic: PlannedTopic,
+        previous_torrent_id: TorrentId,
+    },
+    Delete {
+        topic_id: String,
+        title: String,
+        torrent_id: Option<TorrentId>,
+    },
+    MarkFinished {
+        topic_id: String,
+        title: String,
+    },
+    /// Topic isn't watched anymore, its data is kept during the grace period.
+    MarkPendingDeletion {
+        topic_id: String,
+        title: String,
+    },
+    /// Topic pending deletion is watched again.
+    Restore {
+        topic_id: String,
+        title: String,
+        torrent_id: Option<TorrentId>,
+    },
+    /// Topic tracked without downloading was registered again.
+    Track {
+        #[serde(flatten)]
+        topic: PlannedTopic,
+    },
+}
+
+impl SyncAction {
+    /// Name of the action, same as in the serialized plan.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Self::Add { .. } => "add",
+            Self::Update { .. } => "update",
+            Self::Delete { .. } => "delete",
+            Self::MarkFinished { .. } => "mark_finished",
+            Self::MarkPendingDeletion { .. } => "mark_pending_deletion",
+            Self::Restore { .. } => "restore",
+            Self::Track { .. } => "track",
+        }
+    }
+
+    /// Whether the action deletes the topic, now or once the grace period passes.
+    pub(crate) fn is_deletion(&self) -> bool {
+        matches!(self, Self::Delete { .. } | Self::MarkPendingDeletion { .. })
+    }
+
+    pub(crate) fn topic_id(&self) -> &str {
+        match self {
+            Self::Add { topic } | Self::Update { topic, .. } | Self::Track { topic } => {
+                &topic.topic_id
+            }
+            Self::Delete { topic_id, .. }
+            | Self::MarkFinished { topic_id, .. }
+            | Self::MarkPendingDeletion { topic_id, .. }
+            | Self::Restore { topic_id, .. } => topic_id,
+        }
+    }
+
+    pub(crate) fn title(&self) -> &str {
+        match self {
+            Self::Add { topic } | Self::Update { topic, .. } | Self::Track { topic } => {
+                &topic.title
+            }
+            Self::Delete { title, .. }
+            | Self::MarkFinished { title, .. }
+            | Self::MarkPendingDeletion { title, .. }
+            | Self::Restore { title, .. } => title,
+        }
+    }
+}
+
+impl Display for SyncAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Add { topic } => write!(
+                f,
+                "+ add      [{}] {} (topic {})",
+                topic.category, topic.title, topic.topic_id
+            ),
+            Self::Update { topic, .. } => write!(
+                f,
+                "~ update   [{}] {} (topic {})",
+                topic.category, topic.title, topic.topic_id
+            ),
+            Self::Delete {
+                topic_id, title, ..
+            } => {
+                write!(f, "- delete   {} (topic {})", title, topic_id)
+            }
+            Self::MarkFinished { topic_id, title } => {
+                write!(f, "✓ finished {} (topic {})", title, topic_id)
+            }
+            Self::MarkPendingDeletion { topic_id, title } => {
+                write!(f, "! pending  {} (topic {})", title, topic_id)
+            }
+            Self::Restore {
+                topic_id, title, ..
+            } => {
+                write!(f, "↺ restore  {} (topic {})", title, topic_id)
+            }
+            Self::Track { topic } => write!(
+                f,
+                "* track    [{}] {} (topic {})",
+                topic.category, topic.title, topic.topic_id
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct SyncPlan {
+    pub(crate) actions: Vec<SyncAction>,
+    /// Deletions held back by [`DeletionGuard`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) blocked_deletions: Vec<SyncAction>,
+    /// Bytes that incomplete torrents of the tasks still have to download.
+    #[serde(skip)]
+    pub(crate) committed_bytes: u64,
+}
+
+impl Display for SyncPlan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if !self.blocked_deletions.is_empty() {
+            writeln!(
+                f,
+                "{} deletion(s) blocked, run `sync --allow-mass-deletion` to apply them:",
+                self.blocked_deletions.len()
+            )?;
+
+            for action in self.blocked_deletions.iter() {
+                writeln!(f, "  {}", action)?;
+            }
+        }
+
+        if self.actions.is_empty() {
+            return writeln!(f, "Nothing to do");
+        }
+
+        for action in self.actions.iter() {
+            writeln!(f, "{}", action)?;
+        }
+
+        writeln!(f, "{} action(s) planned", self.actions.len())
+    }
+}
+
+/// Decides what a sync run does, in the order it does it.
+/// `finished_topic_ids` are topics whose torrents are already downloaded.
+///
+/// Topics waiting for a retry after a failure are skipped. Permanently failed topics
+/// are only deleted, once they're not watched anymore.
+///
+/// Unwatched topics are deleted once `deletion_grace_period` passes, and restored
+/// if they're watched again before that.
+///
+/// [`TaskOverrides`](crate::task_db::TaskOverrides) of the tasks are respected: pinned tasks are never deleted outside
+/// of wipeout mode, tasks skipping updates are never updated, and tasks tracked only
+/// are never downloaded.
+pub(crate) fn build_plan(
+    tasks: &[Task],
+    watched_topics: &[Topic],
+    finished_topic_ids: &HashSet<String>,
+    wipeout_mode: bool,
+    deletion_grace_period: Duration,
+    now: DateTime<Utc>,
+) -> SyncPlan {
+    let mut plan = SyncPlan::default();
+
+    if !wipeout_mode {
+        for topic in watched_topics.iter() {
+            let matched_task = tasks
+                .iter()
+                .find(|t| t.topic_id == topic.topic_meta.topic_id);
+
+            match matched_task {
+                Some(task) if matches!(task.task_status, TaskStatus::PendingDeletion) => {
+                    plan.actions.push(SyncAction::Restore {
+                        topic_id: task.topic_id.clone(),
+                        title: task.topic_title.clone(),
+                        torrent_id: task.transmission_torrent_id.clone(),
+                    })
+                }
+                Some(task)
+                    if is_backing_off(task, now)
+                        || matches!(task.task_status, TaskStatus::PermanentlyFailed) => {}
+                Some(task) if task.overrides.track_only => {
+                    if task.topic_download_registered_at != topic.download_meta.registered_at {
+                        plan.actions.push(SyncAction::Track {
+                            topic: topic.into(),
+                        });
+                    }
+                }
+                // Torrent of a new topic that failed to be added is added again.
+                Some(Task {
+                    transmission_torrent_id: None,
+                    overrides,
+                    ..
+                }) => plan.actions.push(SyncAction::Add {
+                    topic: PlannedTopic {
+                        directory: overrides.directory.clone(),
+                        ..topic.into()
+                    },
+                }),
+                None => plan.actions.push(SyncAction::Add {
+                    topic: topic.into(),
+                }),
+                Some(task)
+                    if task.topic_download_registered_at == topic.download_meta.registered_at =>
+                {
+                    if matches!(task.task_status, TaskStatus::Added)
+                        && finished_topic_ids.contains(&task.topic_id)
+                    {
+                        plan.actions.push(SyncAction::MarkFinished {
+                            topic_id: task.topic_id.clone(),
+                            title: task.topic_title.clone(),
+                        });
+                    }
+                }
+                Some(task) if task.overrides.skip_updates => {}
+                Some(Task {
+                    transmission_torrent_id: Some(previous_torrent_id),
+                    overrides,
+                    ..
+                }) => plan.actions.push(SyncAction::Update {
+                    topic: PlannedTopic {
+                        directory: overrides.directory.clone(),
+                        ..topic.into()
+                    },
+                    previous_torrent_id: previous_torrent_id.clone(),
+                }),
+            }
+        }
+    }
+
+    let watched_topics_ids = watched_topics
+        .iter()
+        .map(|t| t.topic_meta.topic_id.as_str())
+        .collect::<HashSet<_>>();
+
+    for task in tasks
+        .iter()
+        .filter(|t| !watched_topics_ids.contains(t.topic_id.as_str()))
+        .filter(|t| !is_placeholder(t))
+        .filter(|t| !is_backing_off(t, now))
+        .filter(|t| wipeout_mode || !t.overrides.pinned)
+    {
+        let is_pending_deletion = matches!(task.task_status, TaskStatus::PendingDeletion);
+        let pending_deletion_since = task
+            .pending_deletion_since
+            .filter(|_| is_pending_deletion)
+            .unwrap_or(now);
+
+        if pending_deletion_since + deletion_grace_period <= now {
+            plan.actions.push(SyncAction::Delete {
+                topic_id: task.topic_id.clone(),
+                title: task.topic_title.clone(),
+                torrent_id: task.transmission_torrent_id.clone(),
+            });
+        } else if !is_pending_deletion {
+            plan.actions.push(SyncAction::MarkPendingDeletion {
+                topic_id: task.topic_id.clone(),
+                title: task.topic_title.clone(),
+            });
+        }
+    }
+
+    plan
+}
+
+/// Whether the task only keeps overrides set before its topic was ever synced.
+/// Such tasks have nothing to delete.
+fn is_placeholder(task: &Task) -> bool {
+    task.transmission_torrent_id.is_none() && task.topic_download_registered_at.is_empty()
+}
+
+/// Moves all deletions out of the plan if there are more of them than the guard allows.
+/// Topics pending deletion count as well, so a broken watched list is caught on the run
+/// that first sees it, not once the grace period passes.
+pub(crate) fn guard_deletions(
+    mut plan: SyncPlan,
+    tasks_count: usize,
+    deletion_guard: &DeletionGuard,
+) -> SyncPlan {
+    let deletions = plan
+        .actions
+        .iter()
+        .filter(|action| action.is_deletion())
+        .count();
+
+    if deletion_guard.allows(deletions, tasks_count) {
+        return plan;
+    }
+
+    let (blocked_deletions, actions) = plan.actions.into_iter().partition(SyncAction::is_deletion);
+
+    plan.actions = actions;
+    plan.blocked_deletions = blocked_deletions;
+
+    plan
+}
+
+/// Computes the sync plan using read-only requests only: no torrent files are downloaded,
+/// and neither the download client nor the storage is changed.
+pub(crate) async fn plan_sync(
+    toloka_client: &toloka::TolokaClient,
+    download_client: &dyn DownloadClient,
+    task_db: &dyn TaskDb,
+    wipeout_mode: bool,
+    deletion_grace_period: Duration,
+    deletion_guard: &DeletionGuard,
+    timings: &Timings,
+) -> Result<SyncPlan, SyncError> {
+    debug!("Loading tasks...");
+    let tasks = task_db.get_tasks()?;
+
+    debug!("Loading watched topics...");
+    let watched_topics = timings.toloka(toloka_client.get_watched_topics()).await?;
+
+    debug!("Checking downloaded torrents...");
+    let mut finished_topic_ids = HashSet::new();
+    let mut committed_bytes = 0u64;
+    for (task, torrent_id) in tasks
+        .iter()
+        .filter(|t| !wipeout_mode && matches!(t.task_status, TaskStatus::Added))
+        .filter_map(|t| Some((t, t.transmission_torrent_id.as_ref()?)))
+    {
+        let torrent_id = torrent_id.into();
+        let Some(status) = download_client.get_status(&torrent_id).await? else {
+            continue;
+        };
+
+        if status.is_finished {
+            finished_topic_ids.insert(task.topic_id.clone());
+        } else {
+            committed_bytes = committed_bytes.saturating_add(status.left_until_done);
+        }
+    }
+
+    let mut plan = plan_known_topics(
+        tasks,
+        &watched_topics,
+        &finished_topic_ids,
+        wipeout_mode,
+        deletion_grace_period,
+        deletion_guard,
+        Utc::now(),
+    );
+    plan.committed_bytes = committed_bytes;
+
+    Ok(plan)
+}
+
+/// Plans the sync of tasks whose topics were loaded. Topics that couldn't be loaded
+/// are still watched, so their tasks are kept as they are, unless everything is deleted
+/// anyway. The deletion guard measures deletions against the same tasks they're planned
+/// for, so unknown topics don't make a broken watched list look less broken.
+fn plan_known_topics(
+    tasks: Vec<Task>,
+    watched_topics: &WatchedTopics,
+    finished_topic_ids: &HashSet<String>,
+    wipeout_mode: bool,
+    deletion_grace_period: Duration,
+    deletion_guard: &DeletionGuard,
+    now: DateTime<Utc>,
+) -> SyncPlan {
+    let tasks = tasks
+        .into_iter()
+        .filter(|task| wipeout_mode || !watched_topics.unknown_topic_ids.contains(&task.topic_id))
+        .collect::<Vec<_>>();
+
+    let plan = build_plan(
+        &tasks,
+        &watched_topics.topics,
+        finished_topic_ids,
+        wipeout_mode,
+        deletion_grace_period,
+        now,
+    );
+
+    guard_deletions(plan, tasks.len(), deletion_guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use torrent_bot_clients::toloka::types::{DownloadMeta, TopicMeta};
+
+    use super::*;
+
+    use crate::task_db::TaskOverrides;
+
+    fn topic(topic_id: &str, registered_at: &str) -> Topic {
+        Topic {
+            topic_meta: TopicMeta {
+                topic_id: topic_id.to_string(),
+                title: format!("Topic {}", topic_id),
+                category: Category::Series,
+            },
+            download_meta: DownloadMeta {
+                registered_at: registered_at.to_string(),
+                download_id: format!("download-{}", topic_id),
+                poster_url: None,
+            },
+        }
+    }
+
+    fn task(topic_id: &str, registered_at: &str, task_status: TaskStatus) -> Task {
+        Task {
+            topic_id: topic_id.to_string(),
+            topic_title: format!("Topic {}", topic_id),
+            previous_title: None,
+            topic_download_registered_at: registered_at.to_string(),
+            transmission_torrent_id: Some(TorrentId::Hash(format!("hash-{}", topic_id))),
+            torrent_hash: None,
+            category: None,
+            size: None,
+            poster_url: None,
+            task_status,
+            last_error: None,
+            attempts: 0,
+            next_retry_at: None,
+            pending_deletion_since: None,
+            overrides: TaskOverrides::default(),
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        "2024-03-01T12:00:00Z".parse().unwrap()
+    }
+
+    fn planned_topic(topic_id: &str, registered_at: &str) -> PlannedTopic {
+        (&topic(topic_id, registered_at)).into()
+    }
+
+    fn finished(topic_ids: &[&str]) -> HashSet<String> {
+        topic_ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn test_nothing_to_do_without_topics_and_tasks() {
+        let plan = build_plan(&[], &[], &HashSet::new(), false, Duration::zero(), now());
+
+        assert_eq!(plan, SyncPlan::default());
+    }
+
+    #[test]
+    fn test_new_topic_is_added() {
+        let topics = vec![topic("1", "2024-01-01")];
+
+        let plan = build_plan(
+            &[],
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Add {
+                topic: planned_topic("1", "2024-01-01"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_reregistered_topic_is_updated() {
+        let tasks = vec![task("1", "2024-01-01", TaskStatus::Finished)];
+        let topics = vec![topic("1", "2024-02-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Update {
+                topic: planned_topic("1", "2024-02-01"),
+                previous_torrent_id: TorrentId::Hash("hash-1".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_updated_topic_is_not_marked_finished() {
+        let tasks = vec![task("1", "2024-01-01", TaskStatus::Added)];
+        let topics = vec![topic("1", "2024-02-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &finished(&["1"]),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert!(matches!(plan.actions[..], [SyncAction::Update { .. }]));
+    }
+
+    #[test]
+    fn test_unchanged_topic_in_progress_is_left_alone() {
+        let tasks = vec![task("1", "2024-01-01", TaskStatus::Added)];
+        let topics = vec![topic("1", "2024-01-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(plan, SyncPlan::default());
+    }
+
+    #[test]
+    fn test_downloaded_topic_is_marked_finished() {
+        let tasks = vec![task("1", "2024-01-01", TaskStatus::Added)];
+        let topics = vec![topic("1", "2024-01-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &finished(&["1"]),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::MarkFinished {
+                topic_id: "1".to_string(),
+                title: "Topic 1".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_finished_topic_is_not_marked_again() {
+        let tasks = vec![task("1", "2024-01-01", TaskStatus::Finished)];
+        let topics = vec![topic("1", "2024-01-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &finished(&["1"]),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(plan, SyncPlan::default());
+    }
+
+    #[test]
+    fn test_topic_that_failed_to_be_added_is_added_again() {
+        let tasks = vec![Task {
+            transmission_torrent_id: None,
+            last_error: Some("Unexpected status code: 404 Not Found".to_string()),
+            ..task("1", "2024-01-01", TaskStatus::Added)
+        }];
+        let topics = vec![topic("1", "2024-01-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Add {
+                topic: planned_topic("1", "2024-01-01"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_failed_topic_is_skipped_until_retry_time() {
+        let failed_task = Task {
+            transmission_torrent_id: None,
+            attempts: 1,
+            next_retry_at: Some(now() + Duration::minutes(1)),
+            ..task("1", "2024-01-01", TaskStatus::Added)
+        };
+        let topics = vec![topic("1", "2024-01-01")];
+
+        let plan = build_plan(
+            std::slice::from_ref(&failed_task),
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(plan, SyncPlan::default());
+
+        let plan = build_plan(
+            &[failed_task],
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            now() + Duration::minutes(1),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Add {
+                topic: planned_topic("1", "2024-01-01"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_failed_update_is_skipped_until_retry_time() {
+        let tasks = vec![Task {
+            attempts: 2,
+            next_retry_at: Some(now() + Duration::minutes(1)),
+            ..task("1", "2024-01-01", TaskStatus::Added)
+        }];
+        let topics = vec![topic("1", "2024-02-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &finished(&["1"]),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(plan, SyncPlan::default());
+    }
+
+    #[test]
+    fn test_permanently_failed_topic_is_not_retried() {
+        let tasks = vec![
+            Task {
+                transmission_torrent_id: None,
+                attempts: 5,
+                ..task("1", "2024-01-01", TaskStatus::PermanentlyFailed)
+            },
+            Task {
+                attempts: 5,
+                ..task("2", "2024-01-01", TaskStatus::PermanentlyFailed)
+            },
+        ];
+        let topics = vec![topic("1", "2024-01-01"), topic("2", "2024-02-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &finished(&["2"]),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(plan, SyncPlan::default());
+    }
+
+    #[test]
+    fn test_permanently_failed_topic_is_deleted_when_unwatched() {
+        let tasks = vec![Task {
+            attempts: 5,
+            ..task("1", "2024-01-01", TaskStatus::PermanentlyFailed)
+        }];
+
+        let plan = build_plan(&tasks, &[], &HashSet::new(), false, Duration::zero(), now());
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Delete {
+                topic_id: "1".to_string(),
+                title: "Topic 1".to_string(),
+                torrent_id: Some(TorrentId::Hash("hash-1".to_string())),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_failed_deletion_of_permanently_failed_topic_backs_off() {
+        let tasks = vec![Task {
+            attempts: 6,
+            next_retry_at: Some(now() + Duration::hours(24)),
+            ..task("1", "2024-01-01", TaskStatus::PermanentlyFailed)
+        }];
+
+        let plan = build_plan(&tasks, &[], &HashSet::new(), false, Duration::zero(), now());
+
+        assert_eq!(plan, SyncPlan::default());
+    }
+
+    #[test]
+    fn test_placeholder_task_is_kept_when_unwatched() {
+        let tasks = vec![Task {
+            topic_download_registered_at: String::new(),
+            transmission_torrent_id: None,
+            overrides: TaskOverrides {
+                directory: Some("Cartoons".to_string()),
+                ..TaskOverrides::default()
+            },
+            ..task("1", "", TaskStatus::Added)
+        }];
+
+        let plan = build_plan(&tasks, &[], &HashSet::new(), false, Duration::zero(), now());
+
+        assert_eq!(plan, SyncPlan::default());
+    }
+
+    #[test]
+    fn test_unwatched_topic_is_pending_deletion_during_grace_period() {
+        let tasks = vec![task("1", "2024-01-01", TaskStatus::Finished)];
+
+        let plan = build_plan(
+            &tasks,
+            &[],
+            &HashSet::new(),
+            false,
+            Duration::days(3),
+            now(),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::MarkPendingDeletion {
+                topic_id: "1".to_string(),
+                title: "Topic 1".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_pending_deletion_is_kept_until_grace_period_ends() {
+        let tasks = vec![Task {
+            pending_deletion_since: Some(now() - Duration::days(2)),
+            ..task("1", "2024-01-01", TaskStatus::PendingDeletion)
+        }];
+
+        let plan = build_plan(
+            &tasks,
+            &[],
+            &HashSet::new(),
+            false,
+            Duration::days(3),
+            now(),
+        );
+
+        assert_eq!(plan, SyncPlan::default());
+
+        let plan = build_plan(
+            &tasks,
+            &[],
+            &HashSet::new(),
+            false,
+            Duration::days(3),
+            now() + Duration::days(1),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Delete {
+                topic_id: "1".to_string(),
+                title: "Topic 1".to_string(),
+                torrent_id: Some(TorrentId::Hash("hash-1".to_string())),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_watched_again_topic_is_restored() {
+        let tasks = vec![Task {
+            pending_deletion_since: Some(now() - Duration::days(2)),
+            ..task("1", "2024-01-01", TaskStatus::PendingDeletion)
+        }];
+        let topics = vec![topic("1", "2024-01-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::days(3),
+            now(),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Restore {
+                topic_id: "1".to_string(),
+                title: "Topic 1".to_string(),
+                torrent_id: Some(TorrentId::Hash("hash-1".to_string())),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unwatched_topic_is_deleted() {
+        let tasks = vec![
+            task("1", "2024-01-01", TaskStatus::Added),
+            task("2", "2024-01-01", TaskStatus::Finished),
+        ];
+        let topics = vec![topic("1", "2024-01-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Delete {
+                topic_id: "2".to_string(),
+                title: "Topic 2".to_string(),
+                torrent_id: Some(TorrentId::Hash("hash-2".to_string())),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_wipeout_mode_only_deletes() {
+        let tasks = vec![
+            task("1", "2024-01-01", TaskStatus::Added),
+            task("2", "2024-01-01", TaskStatus::Added),
+            task("3", "2024-01-01", TaskStatus::Added),
+        ];
+        let topics = vec![
+            topic("1", "2024-02-01"),
+            topic("2", "2024-01-01"),
+            topic("4", "2024-01-01"),
+        ];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &finished(&["2"]),
+            true,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Delete {
+                topic_id: "3".to_string(),
+                title: "Topic 3".to_string(),
+                torrent_id: Some(TorrentId::Hash("hash-3".to_string())),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_actions_follow_topics_order_and_deletions_go_last() {
+        let tasks = vec![
+            task("1", "2024-01-01", TaskStatus::Added),
+            task("2", "2024-01-01", TaskStatus::Added),
+            task("3", "2024-01-01", TaskStatus::Finished),
+            task("4", "2024-01-01", TaskStatus::Added),
+        ];
+        let topics = vec![
+            topic("5", "2024-01-01"),
+            topic("1", "2024-01-01"),
+            topic("2", "2024-02-01"),
+            topic("3", "2024-01-01"),
+        ];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &finished(&["1", "3"]),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![
+                SyncAction::Add {
+                    topic: planned_topic("5", "2024-01-01"),
+                },
+                SyncAction::MarkFinished {
+                    topic_id: "1".to_string(),
+                    title: "Topic 1".to_string(),
+                },
+                SyncAction::Update {
+                    topic: planned_topic("2", "2024-02-01"),
+                    previous_torrent_id: TorrentId::Hash("hash-2".to_string()),
+                },
+                SyncAction::Delete {
+                    topic_id: "4".to_string(),
+                    title: "Topic 4".to_string(),
+                    torrent_id: Some(TorrentId::Hash("hash-4".to_string())),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mass_deletion_is_blocked() {
+        let tasks = vec![
+            task("1", "2024-01-01", TaskStatus::Finished),
+            task("2", "2024-01-01", TaskStatus::Finished),
+            task("3", "2024-01-01", TaskStatus::Finished),
+        ];
+        let topics = vec![topic("4", "2024-01-01")];
+        let deletion_guard = DeletionGuard {
+            max_count: 10,
+            max_percent: 50,
+            allow_mass_deletion: false,
+        };
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            now(),
+        );
+        let plan = guard_deletions(plan, tasks.len(), &deletion_guard);
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Add {
+                topic: planned_topic("4", "2024-01-01"),
+            }]
+        );
+        assert_eq!(plan.blocked_deletions.len(), 3);
+
+        let deletion_guard = DeletionGuard {
+            allow_mass_deletion: true,
+            ..deletion_guard
+        };
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            now(),
+        );
+        let plan = guard_deletions(plan, tasks.len(), &deletion_guard);
+
+        assert_eq!(plan.actions.len(), 4);
+        assert!(plan.blocked_deletions.is_empty());
+    }
+
+    #[test]
+    fn test_mass_pending_deletion_is_blocked() {
+        let tasks = vec![
+            task("1", "2024-01-01", TaskStatus::Finished),
+            task("2", "2024-01-01", TaskStatus::Finished),
+            Task {
+                pending_deletion_since: Some(now() - Duration::days(4)),
+                ..task("3", "2024-01-01", TaskStatus::PendingDeletion)
+            },
+            task("4", "2024-01-01", TaskStatus::Finished),
+        ];
+        let topics = vec![topic("4", "2024-01-01")];
+        let deletion_guard = DeletionGuard {
+            max_count: 10,
+            max_percent: 50,
+            allow_mass_deletion: false,
+        };
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::days(3),
+            now(),
+        );
+        let plan = guard_deletions(plan, tasks.len(), &deletion_guard);
+
+        assert!(plan.actions.is_empty());
+        assert_eq!(
+            plan.blocked_deletions
+                .iter()
+                .map(SyncAction::kind)
+                .collect::<Vec<_>>(),
+            vec!["mark_pending_deletion", "mark_pending_deletion", "delete"]
+        );
+    }
+
+    #[test]
+    fn test_deletion_guard_ignores_unknown_topics() {
+        let tasks = ["1", "2", "3", "4", "5", "6"]
+            .map(|topic_id| task(topic_id, "2024-01-01", TaskStatus::Finished))
+            .to_vec();
+        let watched_topics = WatchedTopics {
+            topics: vec![topic("6", "2024-01-01")],
+            unknown_topic_ids: vec!["1".to_string(), "2".to_string(), "3".to_string()],
+        };
+        let deletion_guard = DeletionGuard {
+            max_count: 10,
+            max_percent: 50,
+            allow_mass_deletion: false,
+        };
+
+        // Two deletions are a third of all tasks, but two thirds of the known ones.
+        let plan = plan_known_topics(
+            tasks,
+            &watched_topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            &deletion_guard,
+            now(),
+        );
+
+        assert!(plan.actions.is_empty());
+        assert_eq!(
+            plan.blocked_deletions
+                .iter()
+                .map(SyncAction::topic_id)
+                .collect::<Vec<_>>(),
+            vec!["4", "5"]
+        );
+    }
+
+    #[test]
+    fn test_plan_serialization() {
+        let plan = SyncPlan {
+            actions: vec![
+                SyncAction::Update {
+                    topic: planned_topic("2", "2024-02-01"),
+                    previous_torrent_id: TorrentId::Id(7),
+                },
+                SyncAction::Delete {
+                    topic_id: "4".to_string(),
+                    title: "Topic 4".to_string(),
+                    torrent_id: Some(TorrentId::Hash("hash-4".to_string())),
+                },
+            ],
+            ..SyncPlan::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(&plan).unwrap(),
+            serde_json::json!({
+                "actions": [
+                    {
+                        "action": "update",
+                        "topic_id": "2",
+                        "title": "Topic 2",
+                        "category": "Series",
+                        "download_id": "download-2",
+                        "registered_at": "2024-02-01",
+                        "previous_torrent_id": 7
+                    },
+                    {
+                        "action": "delete",
+                        "topic_id": "4",
+                        "title": "Topic 4",
+                        "torrent_id": "hash-4"
+                    }
+                ]
+            })
+        );
+    }
+
+    fn with_overrides(task: Task, overrides: TaskOverrides) -> Task {
+        Task { overrides, ..task }
+    }
+
+    #[test]
+    fn test_pinned_task_is_not_deleted() {
+        let tasks = vec![with_overrides(
+            task("1", "2024-01-01", TaskStatus::Finished),
+            TaskOverrides {
+                pinned: true,
+                ..TaskOverrides::default()
+            },
+        )];
+
+        let plan = build_plan(&tasks, &[], &HashSet::new(), false, Duration::zero(), now());
+        assert_eq!(plan.actions, vec![]);
+
+        let plan = build_plan(&tasks, &[], &HashSet::new(), true, Duration::zero(), now());
+        assert!(matches!(plan.actions[..], [SyncAction::Delete { .. }]));
+    }
+
+    #[test]
+    fn test_task_skipping_updates_is_not_updated() {
+        let tasks = vec![with_overrides(
+            task("1", "2024-01-01", TaskStatus::Finished),
+            TaskOverrides {
+                skip_updates: true,
+                ..TaskOverrides::default()
+            },
+        )];
+        let topics = vec![topic("1", "2024-02-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(plan.actions, vec![]);
+    }
+
+    #[test]
+    fn test_tracked_task_is_not_downloaded() {
+        let overrides = TaskOverrides {
+            track_only: true,
+            ..TaskOverrides::default()
+        };
+        let tasks = vec![
+            with_overrides(
+                task("1", "2024-01-01", TaskStatus::Added),
+                overrides.clone(),
+            ),
+            with_overrides(
+                Task {
+                    transmission_torrent_id: None,
+                    ..task("2", "", TaskStatus::Added)
+                },
+                overrides,
+            ),
+        ];
+        let topics = vec![topic("1", "2024-01-01"), topic("2", "2024-02-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &finished(&["1"]),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Track {
+                topic: planned_topic("2", "2024-02-01"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_task_directory_is_planned() {
+        let overrides = TaskOverrides {
+            directory: Some("Cartoons".to_string()),
+            ..TaskOverrides::default()
+        };
+        let tasks = vec![
+            with_overrides(
+                task("1", "2024-01-01", TaskStatus::Finished),
+                overrides.clone(),
+            ),
+            with_overrides(
+                Task {
+                    transmission_torrent_id: None,
+                    ..task("2", "", TaskStatus::Added)
+                },
+                overrides,
+            ),
+        ];
+        let topics = vec![topic("1", "2024-02-01"), topic("2", "2024-02-01")];
+
+        let plan = build_plan(
+            &tasks,
+            &topics,
+            &HashSet::new(),
+            false,
+            Duration::zero(),
+            now(),
+        );
+
+        assert_eq!(
+            plan.actions,
+            vec![
+                SyncAction::Update {
+                    topic: PlannedTopic {
+                        directory: Some("Cartoons".to_string()),
+                        ..planned_topic("1", "2024-02-01")
+                    },
+                    previous_torrent_id: TorrentId::Hash("hash-1".to_string()),
+                },
+                SyncAction::Add {
+                    topic: PlannedTopic {
+                        directory: Some("Cartoons".to_string()),
+                        ..planned_topic("2", "2024-02-01")
+                    },
+                },
+            ]
+        );
+    }
+}
@@ -1,13 +1,29 @@
-use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 
+use chrono::{Duration, Utc};
 use thiserror::Error;
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
 
+use torrent_bot_clients::download_client::{
+    DownloadClient, DownloadClientError, RemoveStrategy, TorrentId,
+};
 use torrent_bot_clients::toloka;
-use torrent_bot_clients::transmission;
+use torrent_bot_clients::torrent_file::{info_hash, total_length};
 
 use crate::client::Client;
-use crate::task_db::{StorageError, Task, TaskDb, TaskStatus};
+use crate::daemon::Shutdown;
+use crate::deletion_guard::DeletionGuard;
+use crate::event_log::{torrent_hash, Event, EventFilter, EventKind};
+use crate::file_layout::{plan_layout_upgrade, LayoutUpgrade};
+use crate::file_rules::FileSelectionConfig;
+use crate::notification::{Notification, NotificationKind};
+use crate::path_template::PathTemplates;
+use crate::report::{SyncReport, TimedDownloadClient, Timings};
+use crate::retry::RetryPolicy;
+use crate::sync_plan::{plan_sync, PlannedTopic, SyncAction};
+use crate::task_db::{StorageError, Task, TaskDb, TaskOverrides, TaskStatus, MAX_REPORTS};
+use crate::topic_title::describe_update;
 
 #[derive(Debug, Error)]
 pub(crate) enum SyncError {
@@ -15,120 +31,840 @@ pub(crate) enum SyncError {
     TolokaClientError(#[from] toloka::TolokaClientError),
     #[error("Error happened in storage: {0}")]
     StorageError(#[from] StorageError),
-    #[error("Error happened in transmission client: {0}")]
-    TransmissionError(#[from] transmission::TransmissionClientError),
+    #[error("Error happened in download client: {0}")]
+    DownloadClientError(#[from] DownloadClientError),
+    #[error("Not enough free space: {required} bytes required, {available} bytes available")]
+    NotEnoughSpace { required: u64, available: u64 },
+    #[error("Path template renders an empty download path")]
+    EmptyPath,
 }
 
-pub(crate) async fn sync(
-    toloka_client: toloka::TolokaClient,
-    transmission_client: transmission::TransmissionClient,
-    task_db: TaskDb,
-    client: Client,
-    wipeout_mode: bool,
+impl SyncError {
+    /// Errors that would fail every other topic as well, so there is no point to go on.
+    fn is_systemic(&self) -> bool {
+        matches!(
+            self,
+            Self::StorageError(_)
+                | Self::TolokaClientError(toloka::TolokaClientError::Unauthorized)
+        )
+    }
+}
+
+pub(crate) struct SyncOptions {
+    pub(crate) wipeout_mode: bool,
+    pub(crate) file_selection: FileSelectionConfig,
+    pub(crate) path_templates: PathTemplates,
+    pub(crate) free_space_margin: u64,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) deletion_guard: DeletionGuard,
+    pub(crate) deletion_grace_period: Duration,
+    /// Send notifications of a run as one digest at its end, instead of one by one.
+    /// Alerts, e.g. about failed topics, are collected into the digest too.
+    pub(crate) notification_digest: bool,
+}
+
+/// Options and state shared by the actions of one sync run.
+struct SyncRun<'a> {
+    options: &'a SyncOptions,
+    timings: &'a Timings,
+    /// Bytes that incomplete torrents still have to download, the ones started in this run
+    /// included. Free space of the download directory doesn't account for them yet.
+    committed_bytes: AtomicU64,
+    /// Notifications collected for the digest, sent once the run ends.
+    digest: &'a Mutex<Vec<Notification>>,
+}
+
+impl SyncRun<'_> {
+    /// Sends the notification, or collects it into the digest if the run sends one.
+    async fn notify(&self, client: &Client, notification: Notification) {
+        match self.options.notification_digest {
+            true => self.digest.lock().unwrap().push(notification),
+            false => client.send_notification(&notification).await,
+        }
+    }
+}
+
+async fn apply_file_rules(
+    download_client: &dyn DownloadClient,
+    client: &Client,
+    torrent_id: &TorrentId,
+    topic: &PlannedTopic,
+    run: &SyncRun<'_>,
 ) -> Result<(), SyncError> {
-    debug!("Loading tasks...");
-    let tasks = task_db.get_tasks()?;
+    let rules = run.options.file_selection.for_category(&topic.category);
+
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    let files = download_client.get_files(torrent_id).await?;
+    let selection = rules.select(&files);
+
+    if selection.unwanted.is_empty() {
+        return Ok(());
+    }
+
+    download_client
+        .set_files_wanted(
+            torrent_id,
+            selection.wanted.iter().map(|f| f.index).collect(),
+            selection.unwanted.iter().map(|f| f.index).collect(),
+        )
+        .await?;
 
-    debug!("Loading watched topics...");
-    let watched_topics = toloka_client.get_watched_topics().await?;
-    let watched_topics_ids = watched_topics
+    let skipped_files = selection
+        .unwanted
         .iter()
-        .map(|t| t.topic_meta.topic_id.clone())
-        .collect::<HashSet<_>>();
+        .map(|f| f.name.clone())
+        .collect::<Vec<_>>();
 
-    if !wipeout_mode {
-        debug!("Syncing present topics...");
-        for topic in watched_topics.into_iter() {
-            let matched_task = tasks
-                .iter()
-                .find(|t| t.topic_id == topic.topic_meta.topic_id);
+    info!(?skipped_files, "Skipped files: {}", topic.title);
 
-            match matched_task {
-                Some(task)
-                    if task.topic_download_registered_at == topic.download_meta.registered_at =>
-                {
-                    debug!("Topic unchanged: {}", topic.topic_meta.title);
+    run.notify(
+        client,
+        Notification {
+            skipped_files,
+            ..Notification::new(
+                NotificationKind::FilesSkipped,
+                &topic.topic_id,
+                &topic.title,
+            )
+        },
+    )
+    .await;
 
-                    if matches!(task.task_status, TaskStatus::Added) {
-                        let torrent_id = (&task.transmission_torrent_id).into();
-                        let is_downloaded =
-                            transmission_client.get_is_downloaded(&torrent_id).await?;
+    Ok(())
+}
 
-                        if is_downloaded {
-                            task_db.mark_task_as_finished_by_topic_id(&task.topic_id)?;
+/// Checks that `required` bytes fit into the download directory besides the bytes
+/// committed to other torrents, keeping the configured safety margin free.
+/// The bytes are committed for the rest of the run, so torrents added after this one
+/// don't count on the same space.
+async fn reserve_space(
+    download_client: &dyn DownloadClient,
+    client: &Client,
+    topic: &PlannedTopic,
+    required: u64,
+    run: &SyncRun<'_>,
+) -> Result<(), SyncError> {
+    let committed = run.committed_bytes.load(Ordering::Relaxed);
+    let available = download_client
+        .get_free_space()
+        .await?
+        .saturating_sub(committed);
 
-                            client.send_torrent_downloaded(&task.topic_title).await;
+    if required.saturating_add(run.options.free_space_margin) <= available {
+        run.committed_bytes.fetch_add(required, Ordering::Relaxed);
+        return Ok(());
+    }
 
-                            info!("Torrent downloaded: {}", topic.topic_meta.title);
-                        }
-                    }
-                }
+    warn!(
+        required,
+        available, committed, "Not enough free space: {}", topic.title
+    );
+
+    run.notify(
+        client,
+        Notification {
+            required_space: Some(required),
+            available_space: Some(available),
+            ..Notification::new(
+                NotificationKind::NotEnoughSpace,
+                &topic.topic_id,
+                &topic.title,
+            )
+        },
+    )
+    .await;
+
+    Err(SyncError::NotEnoughSpace {
+        required,
+        available,
+    })
+}
+
+/// Moves the previous version's files to the location and layout of the new torrent,
+/// removes the previous torrent, and rechecks the new one, so it only downloads what's new.
+/// Renaming is best effort: files that can't be reused are downloaded again.
+async fn upgrade_previous_torrent(
+    download_client: &dyn DownloadClient,
+    previous_torrent_id: &TorrentId,
+    torrent_id: &TorrentId,
+    path: &str,
+    upgrade: &LayoutUpgrade,
+) -> Result<(), SyncError> {
+    let previous_status = download_client.get_status(previous_torrent_id).await?;
+    let status = download_client.get_status(torrent_id).await?;
+
+    if let (Some(previous_status), Some(status)) = (previous_status, status) {
+        let previous_dir = previous_status.download_dir.unwrap_or_default();
+        let dir = status.download_dir.unwrap_or_default();
+
+        if previous_dir.trim_end_matches('/') != dir.trim_end_matches('/') {
+            debug!(previous_dir, dir, "Moving previous torrent data");
+            download_client
+                .set_location(previous_torrent_id, path)
+                .await?;
+        }
+
+        for rename in upgrade.renames.iter() {
+            if let Err(error) = download_client
+                .rename_path(previous_torrent_id, &rename.path, &rename.name)
+                .await
+            {
+                warn!(?error, ?rename, "Unable to rename previous torrent file");
+            }
+        }
+    }
+
+    download_client
+        .remove(previous_torrent_id, RemoveStrategy::KeepLocalData)
+        .await?;
+
+    // Previous torrent is gone at this point, so failing here would leave the new one
+    // untracked. Without the recheck, reused files are only downloaded again.
+    if upgrade.reused_bytes > 0 {
+        if let Err(error) = download_client.verify(torrent_id).await {
+            warn!(?error, "Unable to recheck upgraded torrent");
+        }
+    }
+
+    Ok(())
+}
+
+/// Torrent of the topic in the download client, returned by [`add_torrent`].
+struct TopicTorrent {
+    id: TorrentId,
+    /// `None` if the torrent file couldn't be parsed.
+    hash: Option<String>,
+    /// Size in bytes, `None` if the torrent file couldn't be parsed.
+    size: Option<u64>,
+}
+
+/// Plans how files of the previous torrent of the topic are reused by the new one.
+async fn plan_upgrade(
+    download_client: &dyn DownloadClient,
+    previous_torrent_id: Option<&TorrentId>,
+    torrent_id: &TorrentId,
+) -> Result<LayoutUpgrade, SyncError> {
+    let Some(previous_torrent_id) = previous_torrent_id else {
+        return Ok(LayoutUpgrade::default());
+    };
+
+    Ok(plan_layout_upgrade(
+        &download_client.get_files(previous_torrent_id).await?,
+        &download_client.get_files(torrent_id).await?,
+    ))
+}
+
+/// Labels a torrent added in this run with its topic's category, applies file rules to it,
+/// and reserves space for the rest of it. Bytes reused from the previous version
+/// of the torrent don't need any space.
+async fn prepare_new_torrent(
+    download_client: &dyn DownloadClient,
+    client: &Client,
+    topic: &PlannedTopic,
+    torrent: &TopicTorrent,
+    reused_bytes: u64,
+    run: &SyncRun<'_>,
+) -> Result<(), SyncError> {
+    download_client
+        .set_labels(&torrent.id, vec![topic.category.to_string()])
+        .await?;
+
+    apply_file_rules(download_client, client, &torrent.id, topic, run).await?;
+
+    // Client may not know a torrent it has just added yet, so its size is used instead.
+    let left_until_done = download_client
+        .get_status(&torrent.id)
+        .await?
+        .map(|status| status.left_until_done)
+        .or(torrent.size)
+        .unwrap_or_default();
+
+    reserve_space(
+        download_client,
+        client,
+        topic,
+        left_until_done.saturating_sub(reused_bytes),
+        run,
+    )
+    .await
+}
+
+/// Downloads topic's torrent and adds it to the download client paused, with file rules applied.
+/// If the topic was downloaded before, files of the previous torrent are reused.
+///
+/// A torrent added here is removed again when it doesn't fit or can't be prepared,
+/// as no task tracks it yet. A torrent the client had before wasn't added by the bot,
+/// so its files aren't selected, and it's never removed.
+async fn add_torrent(
+    toloka_client: &toloka::TolokaClient,
+    download_client: &dyn DownloadClient,
+    client: &Client,
+    topic: &PlannedTopic,
+    previous_torrent_id: Option<&TorrentId>,
+    run: &SyncRun<'_>,
+) -> Result<TopicTorrent, SyncError> {
+    let path = match &topic.directory {
+        Some(directory) => directory.clone(),
+        None => run
+            .options
+            .path_templates
+            .render(topic)
+            .ok_or(SyncError::EmptyPath)?,
+    };
+    let torrent_data = run
+        .timings
+        .toloka(toloka_client.download(&topic.download_id))
+        .await?;
+    let hash = info_hash(&torrent_data);
+    let size = total_length(&torrent_data);
+    let added = download_client.add(torrent_data, &path).await?;
+    let torrent = TopicTorrent {
+        id: added.id,
+        hash,
+        size,
+    };
+
+    // Same torrent was registered again, so there is nothing to upgrade.
+    if previous_torrent_id == Some(&torrent.id) {
+        return Ok(torrent);
+    }
+
+    let prepared = async {
+        let upgrade = plan_upgrade(download_client, previous_torrent_id, &torrent.id).await?;
+
+        if !added.is_duplicate {
+            prepare_new_torrent(
+                download_client,
+                client,
+                topic,
+                &torrent,
+                upgrade.reused_bytes,
+                run,
+            )
+            .await?;
+        }
+
+        if let Some(previous_torrent_id) = previous_torrent_id {
+            upgrade_previous_torrent(
+                download_client,
+                previous_torrent_id,
+                &torrent.id,
+                &path,
+                &upgrade,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    if let Err(error) = prepared {
+        if !added.is_duplicate {
+            if let Err(remove_error) = download_client
+                .remove(&torrent.id, RemoveStrategy::KeepLocalData)
+                .await
+            {
+                warn!(
+                    ?remove_error,
+                    "Unable to remove torrent that failed to be added"
+                );
+            }
+        }
+
+        return Err(error);
+    }
+
+    Ok(torrent)
+}
+
+/// Overrides of the topic's task, to keep them when the task is replaced.
+fn get_overrides(task_db: &dyn TaskDb, topic_id: &str) -> Result<TaskOverrides, SyncError> {
+    Ok(task_db
+        .get_task_by_topic_id(topic_id)?
+        .map(|task| task.overrides)
+        .unwrap_or_default())
+}
+
+/// Applies single planned action through the clients.
+/// Returns the notification about the topic, which the caller sends or collects into a digest.
+async fn execute_action(
+    toloka_client: &toloka::TolokaClient,
+    download_client: &dyn DownloadClient,
+    task_db: &dyn TaskDb,
+    client: &Client,
+    run: &SyncRun<'_>,
+    action: &SyncAction,
+) -> Result<Option<Notification>, SyncError> {
+    let notification = match action {
+        SyncAction::MarkFinished { topic_id, title } => {
+            task_db.mark_task_as_finished_by_topic_id(topic_id)?;
+
+            let notification = match task_db.get_task_by_topic_id(topic_id)? {
                 Some(task) => {
-                    let torrent_data = toloka_client
-                        .download(&topic.download_meta.download_id)
-                        .await?;
-                    let torrent_id = (&task.transmission_torrent_id).into();
-                    transmission_client
-                        .remove(&torrent_id, transmission::RemoveStrategy::KeepLocalData)
-                        .await?;
-                    let torrent_id = transmission_client
-                        .add(torrent_data, &topic.topic_meta.category.to_string())
-                        .await?;
-
-                    task_db.delete_task_by_topic_id(&topic.topic_meta.topic_id)?;
-                    task_db.add_task(Task {
-                        topic_id: topic.topic_meta.topic_id,
-                        topic_title: topic.topic_meta.title.clone(),
-                        topic_download_registered_at: topic.download_meta.registered_at,
-                        transmission_torrent_id: (&torrent_id).into(),
-                        task_status: TaskStatus::Added,
-                    })?;
-
-                    client.send_topic_updated(&topic.topic_meta.title).await;
-
-                    info!("Topic updated: {}", topic.topic_meta.title);
+                    task_db.append_event(&Event::of_task(EventKind::Finished, &task))?;
+                    Notification::of_task(NotificationKind::Downloaded, &task)
                 }
+                None => Notification::new(NotificationKind::Downloaded, topic_id, title),
+            };
+
+            info!("Torrent downloaded: {}", title);
+
+            Some(notification)
+        }
+        SyncAction::Update {
+            topic,
+            previous_torrent_id,
+        } => {
+            let previous_torrent_id = previous_torrent_id.into();
+            let torrent = add_torrent(
+                toloka_client,
+                download_client,
+                client,
+                topic,
+                Some(&previous_torrent_id),
+                run,
+            )
+            .await?;
+
+            download_client.start(&torrent.id).await?;
+
+            let updated = Task {
+                topic_id: topic.topic_id.clone(),
+                topic_title: topic.title.clone(),
+                previous_title: None,
+                topic_download_registered_at: topic.registered_at.clone(),
+                transmission_torrent_id: Some((&torrent.id).into()),
+                torrent_hash: torrent.hash,
+                category: Some(topic.category.to_string()),
+                size: torrent.size,
+                poster_url: topic.poster_url.clone(),
+                task_status: TaskStatus::Added,
+                last_error: None,
+                attempts: 0,
+                next_retry_at: None,
+                pending_deletion_since: None,
+                overrides: TaskOverrides::default(),
+            };
+            // The task is changed in one transaction, so its overrides can't be lost
+            // to a concurrent `tasks set`.
+            task_db.update_task(&topic.topic_id, &|task| {
+                *task = Task {
+                    previous_title: Some(task.topic_title.clone()),
+                    overrides: task.overrides.clone(),
+                    ..updated.clone()
+                };
+            })?;
+            let task = match task_db.get_task_by_topic_id(&topic.topic_id)? {
+                Some(task) => task,
+                // The task was forgotten meanwhile, but its new torrent must still be tracked.
                 None => {
-                    let torrent_data = toloka_client
-                        .download(&topic.download_meta.download_id)
-                        .await?;
-                    let torrent_id = transmission_client
-                        .add(torrent_data, &topic.topic_meta.category.to_string())
-                        .await?;
-
-                    task_db.add_task(Task {
-                        topic_id: topic.topic_meta.topic_id,
-                        topic_title: topic.topic_meta.title.clone(),
-                        topic_download_registered_at: topic.download_meta.registered_at,
-                        transmission_torrent_id: (&torrent_id).into(),
-                        task_status: TaskStatus::Added,
-                    })?;
-
-                    client.send_topic_added(&topic.topic_meta.title).await;
-
-                    info!("Topic added: {}", topic.topic_meta.title);
+                    task_db.replace_task(updated.clone())?;
+                    updated
                 }
+            };
+            let event = Event::of_task(EventKind::Updated, &task);
+            let notification = Notification {
+                summary: Some(describe_update(
+                    task.previous_title.as_deref().unwrap_or(&topic.title),
+                    &topic.title,
+                )),
+                ..Notification::of_task(NotificationKind::Updated, &task)
+            };
+            task_db.append_event(&event)?;
+
+            info!("Topic updated: {}", topic.title);
+
+            Some(notification)
+        }
+        SyncAction::Add { topic } => {
+            let torrent =
+                add_torrent(toloka_client, download_client, client, topic, None, run).await?;
+
+            download_client.start(&torrent.id).await?;
+
+            // Topic may have a task left from a failed attempt, or one with overrides only.
+            let overrides = get_overrides(task_db, &topic.topic_id)?;
+            let task = Task {
+                topic_id: topic.topic_id.clone(),
+                topic_title: topic.title.clone(),
+                previous_title: None,
+                topic_download_registered_at: topic.registered_at.clone(),
+                transmission_torrent_id: Some((&torrent.id).into()),
+                torrent_hash: torrent.hash,
+                category: Some(topic.category.to_string()),
+                size: torrent.size,
+                poster_url: topic.poster_url.clone(),
+                task_status: TaskStatus::Added,
+                last_error: None,
+                attempts: 0,
+                next_retry_at: None,
+                pending_deletion_since: None,
+                overrides,
+            };
+            let event = Event::of_task(EventKind::Added, &task);
+            let notification = Notification::of_task(NotificationKind::Added, &task);
+            task_db.replace_task(task)?;
+            task_db.append_event(&event)?;
+
+            info!("Topic added: {}", topic.title);
+
+            Some(notification)
+        }
+        SyncAction::MarkPendingDeletion { topic_id, title } => {
+            task_db.mark_task_as_pending_deletion_by_topic_id(topic_id, Utc::now())?;
+
+            run.notify(
+                client,
+                Notification {
+                    deleted_in_hours: Some(run.options.deletion_grace_period.num_hours()),
+                    ..Notification::new(NotificationKind::PendingDeletion, topic_id, title)
+                },
+            )
+            .await;
+
+            info!("Topic pending deletion: {}", title);
+
+            None
+        }
+        SyncAction::Restore {
+            topic_id,
+            title,
+            torrent_id,
+        } => {
+            let is_downloaded = match torrent_id {
+                Some(torrent_id) => download_client
+                    .get_status(&torrent_id.into())
+                    .await?
+                    .is_some_and(|status| status.is_finished),
+                None => false,
+            };
+            let task_status = match is_downloaded {
+                true => TaskStatus::Finished,
+                false => TaskStatus::Added,
+            };
+
+            task_db.restore_task_by_topic_id(topic_id, task_status)?;
+
+            run.notify(
+                client,
+                Notification::new(NotificationKind::Restored, topic_id, title),
+            )
+            .await;
+
+            info!("Topic restored: {}", title);
+
+            None
+        }
+        SyncAction::Track { topic } => {
+            let mut summary = None;
+
+            if let Some(mut task) = task_db.get_task_by_topic_id(&topic.topic_id)? {
+                // Task created by `tasks set` has no title of the topic yet.
+                if !task.topic_download_registered_at.is_empty() {
+                    summary = Some(describe_update(&task.topic_title, &topic.title));
+                    task.previous_title = Some(task.topic_title.clone());
+                }
+                task.topic_title = topic.title.clone();
+                task.topic_download_registered_at = topic.registered_at.clone();
+                task_db.replace_task(task)?;
+            }
+
+            if let Some(summary) = summary {
+                run.notify(
+                    client,
+                    Notification {
+                        category: Some(topic.category.to_string()),
+                        summary: Some(summary),
+                        ..Notification::new(
+                            NotificationKind::Tracked,
+                            &topic.topic_id,
+                            &topic.title,
+                        )
+                    },
+                )
+                .await;
             }
+
+            info!("Tracked topic updated: {}", topic.title);
+
+            None
         }
-    }
+        SyncAction::Delete {
+            topic_id,
+            title,
+            torrent_id,
+        } => {
+            if let Some(torrent_id) = torrent_id {
+                download_client
+                    .remove(&torrent_id.into(), RemoveStrategy::DeleteLocalData)
+                    .await?;
+            }
 
-    debug!("Syncing deleted topics...");
-    for task in tasks
-        .iter()
-        .filter(|t| !watched_topics_ids.contains(&t.topic_id))
-    {
-        let torrent_id = (&task.transmission_torrent_id).into();
-        transmission_client
-            .remove(&torrent_id, transmission::RemoveStrategy::DeleteLocalData)
-            .await?;
-        task_db.delete_task_by_topic_id(&task.topic_id)?;
+            let (event, notification) = match task_db.get_task_by_topic_id(topic_id)? {
+                Some(task) => (
+                    Event::of_task(EventKind::Deleted, &task),
+                    Notification::of_task(NotificationKind::Deleted, &task),
+                ),
+                None => (
+                    Event::new(EventKind::Deleted, topic_id, title),
+                    Notification::new(NotificationKind::Deleted, topic_id, title),
+                ),
+            };
+            task_db.delete_task_by_topic_id(topic_id)?;
+            task_db.append_event(&event)?;
+
+            info!("Topic deleted: {}", title);
 
-        client.send_topic_deleted(&task.topic_title).await;
+            // Nothing was downloaded for a topic without torrent, so there's nothing to report.
+            torrent_id.as_ref().map(|_| notification)
+        }
+    };
+
+    Ok(notification)
+}
+
+/// Counts the failure on the topic's task and schedules the next attempt.
+/// New topics get a task without torrent, so they are added again later.
+/// The topic is reported once it fails too many times.
+async fn record_failure(
+    task_db: &dyn TaskDb,
+    client: &Client,
+    run: &SyncRun<'_>,
+    action: &SyncAction,
+    error: &str,
+) -> Result<(), SyncError> {
+    let task = task_db.get_task_by_topic_id(action.topic_id())?;
 
-        info!("Topic deleted: {}", task.topic_title);
+    task_db.append_event(&Event {
+        torrent_hash: task.as_ref().and_then(torrent_hash),
+        error: Some(error.to_string()),
+        ..Event::new(EventKind::Failed, action.topic_id(), action.title())
+    })?;
+
+    let mut task = match (task, action) {
+        (Some(task), _) => task,
+        (None, SyncAction::Add { topic }) => Task {
+            topic_id: topic.topic_id.clone(),
+            topic_title: topic.title.clone(),
+            previous_title: None,
+            topic_download_registered_at: topic.registered_at.clone(),
+            transmission_torrent_id: None,
+            torrent_hash: None,
+            category: None,
+            size: None,
+            poster_url: None,
+            task_status: TaskStatus::Added,
+            last_error: None,
+            attempts: 0,
+            next_retry_at: None,
+            pending_deletion_since: None,
+            overrides: TaskOverrides::default(),
+        },
+        (None, _) => return Ok(()),
+    };
+
+    let permanently_failed = run
+        .options
+        .retry_policy
+        .record_failure(&mut task, error, Utc::now());
+
+    if permanently_failed {
+        warn!(
+            attempts = task.attempts,
+            "Topic permanently failed: {}", task.topic_title
+        );
+
+        run.notify(
+            client,
+            Notification {
+                attempts: Some(task.attempts),
+                error: Some(error.to_string()),
+                ..Notification::of_task(NotificationKind::Failed, &task)
+            },
+        )
+        .await;
     }
 
-    debug!("Done");
+    task_db.replace_task(task)?;
 
     Ok(())
 }
+
+/// Runs single sync: plans the actions and applies them one by one. A failed action
+/// doesn't stop the others, unless its error is systemic. On shutdown it stops after
+/// the action being applied. The report of the run is saved to the storage and sent
+/// to the server, whether the run succeeded or not.
+pub(crate) async fn sync(
+    toloka_client: &toloka::TolokaClient,
+    download_client: &dyn DownloadClient,
+    task_db: &dyn TaskDb,
+    client: &Client,
+    options: &SyncOptions,
+    shutdown: &Shutdown,
+) -> SyncReport {
+    let mut report = SyncReport::start();
+    let timings = Timings::default();
+    let download_client = TimedDownloadClient::new(download_client, &timings);
+    let digest = Mutex::new(vec![]);
+
+    let result = async {
+        // Fetching watched topics takes a while, so shutdown doesn't wait for it.
+        // Planning changes nothing, so it's safe to drop it halfway.
+        let plan = shutdown.interruptible(plan_sync(
+            toloka_client,
+            &download_client,
+            task_db,
+            options.wipeout_mode,
+            options.deletion_grace_period,
+            &options.deletion_guard,
+            &timings,
+        ));
+        let Some(plan) = plan.await else {
+            info!("Sync interrupted by shutdown");
+            report.interrupted = true;
+            return Ok(());
+        };
+        let plan = plan?;
+        let run = SyncRun {
+            options,
+            timings: &timings,
+            committed_bytes: AtomicU64::new(plan.committed_bytes),
+            digest: &digest,
+        };
+
+        report.count_actions(&plan.actions);
+        report.blocked_deletions = plan.blocked_deletions.len();
+        report.blocked_topic_ids = plan
+            .blocked_deletions
+            .iter()
+            .map(|action| action.topic_id().to_string())
+            .collect();
+
+        if !plan.blocked_deletions.is_empty() {
+            let titles = plan
+                .blocked_deletions
+                .iter()
+                .map(|action| action.title().to_string())
+                .collect::<Vec<_>>();
+
+            error!(?titles, "Too many topics to delete, deletions are skipped");
+
+            // Blocked plan stays the same until the watched list is fixed,
+            // so it's only reported when it changes.
+            let last_blocked_topic_ids = task_db
+                .get_reports(MAX_REPORTS)?
+                .into_iter()
+                .find(SyncReport::is_planned)
+                .map(|last_report| last_report.blocked_topic_ids);
+
+            if last_blocked_topic_ids.as_ref() != Some(&report.blocked_topic_ids) {
+                let notifications = plan
+                    .blocked_deletions
+                    .iter()
+                    .map(|action| {
+                        Notification::new(
+                            NotificationKind::DeletionBlocked,
+                            action.topic_id(),
+                            action.title(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+
+                client.send_deletion_blocked(&notifications).await;
+            }
+        }
+
+        for action in plan.actions.into_iter() {
+            if shutdown.is_requested() {
+                info!("Sync interrupted by shutdown");
+                report.interrupted = true;
+                break;
+            }
+
+            debug!("Applying: {}", action);
+
+            match execute_action(
+                toloka_client,
+                &download_client,
+                task_db,
+                client,
+                &run,
+                &action,
+            )
+            .await
+            {
+                Ok(notification) => {
+                    report.add_result(&action, None);
+
+                    if let Some(notification) = notification {
+                        run.notify(client, notification).await;
+                    }
+                }
+                Err(error) if error.is_systemic() => return Err(error),
+                Err(error) => {
+                    error!(?error, "Unable to sync topic: {}", action.title());
+
+                    let error = error.to_string();
+                    record_failure(task_db, client, &run, &action, &error).await?;
+
+                    report.add_result(&action, Some(error));
+                }
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+
+    if let Err(error) = result {
+        error!("Sync error: {:?}", error);
+        report.error = Some(error.to_string());
+    }
+
+    // Sent even when the run failed, so topics that were synced are reported.
+    let digest = digest.into_inner().unwrap();
+    if !digest.is_empty() {
+        client.send_notification_digest(&digest).await;
+    }
+
+    report.finish(&timings);
+
+    info!(
+        succeeded = report.topics.len() - report.failed().count(),
+        failed = report.failed().count(),
+        toloka_time_ms = report.toloka_time_ms,
+        download_client_time_ms = report.download_client_time_ms,
+        "Sync finished"
+    );
+
+    for failed in report.failed() {
+        warn!(
+            topic_id = failed.topic_id,
+            error = failed.error,
+            "Failed: {}",
+            failed.title
+        );
+    }
+
+    if let Err(error) = task_db.save_report(&report) {
+        error!(?error, "Unable to save sync report");
+    }
+
+    client.send_sync_report(&report).await;
+
+    let events = task_db.get_events(&EventFilter {
+        since: Some(report.started_at),
+        ..EventFilter::default()
+    });
+    match events {
+        Ok(events) if events.is_empty() => {}
+        Ok(events) => client.send_events(&events).await,
+        Err(error) => error!(?error, "Unable to read events of the run"),
+    }
+
+    report
+}
@@ -1,26 +1,171 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
-const TASKS_KEY: &str = "torrent_bot_tasks";
+use crate::config::StorageBackend;
+use crate::event_log::{Event, EventFilter};
+use crate::report::SyncReport;
+use crate::sled_task_db::SledTaskDb;
+use crate::sqlite_task_db::SqliteTaskDb;
 
-pub(crate) struct TaskDb {
-    db: sled::Db,
-}
+/// Reports of older runs are dropped.
+pub(crate) const MAX_REPORTS: usize = 100;
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum StorageError {
     #[error("Storage error: {0}")]
     TaskDbError(#[from] sled::Error),
+    #[error("Storage error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+    #[error(
+        "Unable to read task for topic {topic_id}: {source}. Run `db repair` to remove unreadable tasks"
+    )]
+    InvalidTask {
+        topic_id: String,
+        source: serde_json::Error,
+    },
+    #[error(
+        "Unable to read tasks for topics {}. Run `db repair` to remove unreadable tasks",
+        .0.join(", ")
+    )]
+    UnreadableTasks(Vec<String>),
+    #[error("Unable to read tasks to migrate: {0}")]
+    InvalidTaskList(serde_json::Error),
+    #[error(
+        "Storage has schema version {version}, but only versions up to {supported} are supported"
+    )]
+    UnsupportedSchemaVersion { version: u32, supported: u32 },
+    #[error("Storage has an invalid schema version")]
+    InvalidSchemaVersion,
+    #[error(
+        "Storage {0} is locked by another runner, e.g. one in daemon mode. Stop it to run commands, or use the SQLite backend"
+    )]
+    Locked(String),
+}
+
+pub(crate) type StorageResult<T> = Result<T, StorageError>;
+
+/// Opens the storage with the backend chosen in config.
+/// Fails if its schema isn't supported or some tasks can't be read.
+pub(crate) fn open_task_db(backend: &StorageBackend, path: &str) -> StorageResult<Box<dyn TaskDb>> {
+    let task_db = create_task_db(backend, path)?;
+
+    let invalid_topic_ids = task_db.get_invalid_topic_ids()?;
+    if !invalid_topic_ids.is_empty() {
+        return Err(StorageError::UnreadableTasks(invalid_topic_ids));
+    }
+
+    Ok(task_db)
 }
 
-type StorageResult<T> = Result<T, StorageError>;
+/// Opens the storage like [`open_task_db`], but only logs unreadable tasks.
+/// Used by `db repair` and `db export`, which must work on such a storage.
+pub(crate) fn open_task_db_lenient(
+    backend: &StorageBackend,
+    path: &str,
+) -> StorageResult<Box<dyn TaskDb>> {
+    let task_db = create_task_db(backend, path)?;
+
+    for topic_id in task_db.get_invalid_topic_ids()? {
+        warn!(
+            topic_id,
+            "Unable to read task. Run `db repair` to remove unreadable tasks"
+        );
+    }
+
+    Ok(task_db)
+}
+
+fn create_task_db(backend: &StorageBackend, path: &str) -> StorageResult<Box<dyn TaskDb>> {
+    Ok(match backend {
+        StorageBackend::Sled => Box::new(SledTaskDb::create(path)?),
+        StorageBackend::Sqlite => Box::new(SqliteTaskDb::create(path)?),
+    })
+}
+
+/// Storage of tasks, sync reports and the event log.
+pub(crate) trait TaskDb: Send + Sync {
+    /// Returns all tasks, ordered by topic id.
+    fn get_tasks(&self) -> StorageResult<Vec<Task>>;
+
+    fn get_task_by_topic_id(&self, topic_id: &str) -> StorageResult<Option<Task>>;
+
+    /// Returns topic ids of tasks that can't be read, e.g. ones written by a newer runner.
+    fn get_invalid_topic_ids(&self) -> StorageResult<Vec<String>>;
+
+    /// Saves the task, replacing the one of the same topic if there is one.
+    fn replace_task(&self, task: Task) -> StorageResult<()>;
+
+    fn delete_task_by_topic_id(&self, topic_id: &str) -> StorageResult<()>;
+
+    /// Changes the task of the topic in a transaction, so concurrent changes aren't lost.
+    /// Does nothing if there is no such task.
+    fn update_task(&self, topic_id: &str, update: &dyn Fn(&mut Task)) -> StorageResult<()>;
+
+    /// Replaces all tasks at once.
+    fn save_tasks(&self, tasks: &[Task]) -> StorageResult<()>;
+
+    /// Saves the report of a sync run and drops the oldest ones.
+    fn save_report(&self, report: &SyncReport) -> StorageResult<()>;
+
+    /// Returns up to `limit` most recent sync reports, newest first.
+    fn get_reports(&self, limit: usize) -> StorageResult<Vec<SyncReport>>;
+
+    /// Appends the event to the log. Events are never changed or dropped.
+    fn append_event(&self, event: &Event) -> StorageResult<()>;
+
+    /// Returns events matching the filter, oldest first.
+    fn get_events(&self, filter: &EventFilter) -> StorageResult<Vec<Event>>;
+
+    /// Writes pending changes to disk.
+    fn flush(&self) -> StorageResult<()>;
+
+    #[tracing::instrument(err, skip(self))]
+    fn mark_task_as_finished_by_topic_id(&self, topic_id: &str) -> StorageResult<()> {
+        self.update_task(topic_id, &|task| {
+            task.task_status = TaskStatus::Finished;
+            task.last_error = None;
+            task.attempts = 0;
+            task.next_retry_at = None;
+        })
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn mark_task_as_pending_deletion_by_topic_id(
+        &self,
+        topic_id: &str,
+        since: DateTime<Utc>,
+    ) -> StorageResult<()> {
+        self.update_task(topic_id, &|task| {
+            task.task_status = TaskStatus::PendingDeletion;
+            task.pending_deletion_since = Some(since);
+        })
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    fn restore_task_by_topic_id(
+        &self,
+        topic_id: &str,
+        task_status: TaskStatus,
+    ) -> StorageResult<()> {
+        self.update_task(topic_id, &|task| {
+            task.task_status = task_status.clone();
+            task.pending_deletion_since = None;
+        })
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub(crate) enum TaskStatus {
     Added,
     Finished,
+    /// Sync of the topic failed too many times, it's not retried anymore.
+    PermanentlyFailed,
+    /// Topic isn't watched anymore, its data is deleted after a grace period.
+    PendingDeletion,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(untagged)]
 pub(crate) enum TorrentId {
     Id(i64),
@@ -37,66 +182,324 @@ impl Default for TaskStatus {
 pub(crate) struct Task {
     pub(crate) topic_id: String,
     pub(crate) topic_title: String,
+    /// Title of the topic before its last update.
+    #[serde(default)]
+    pub(crate) previous_title: Option<String>,
     pub(crate) topic_download_registered_at: String,
-    pub(crate) transmission_torrent_id: TorrentId,
+    /// `None` if the torrent couldn't be added yet.
+    #[serde(default)]
+    pub(crate) transmission_torrent_id: Option<TorrentId>,
+    /// Info hash of the torrent file, `None` for tasks added before it was stored.
+    #[serde(default)]
+    pub(crate) torrent_hash: Option<String>,
+    /// Category of the topic, `None` for tasks added before it was stored.
+    #[serde(default)]
+    pub(crate) category: Option<String>,
+    /// Total size of the torrent in bytes, files skipped by file selection rules included.
+    /// `None` for tasks added before it was stored.
+    #[serde(default)]
+    pub(crate) size: Option<u64>,
+    /// Poster image of the topic, if the topic page has one.
+    #[serde(default)]
+    pub(crate) poster_url: Option<String>,
     #[serde(default)]
     pub(crate) task_status: TaskStatus,
+    /// Error of the last failed sync of the topic.
+    #[serde(default)]
+    pub(crate) last_error: Option<String>,
+    /// Failed sync attempts in a row.
+    #[serde(default)]
+    pub(crate) attempts: u32,
+    /// Topic isn't synced again until this time after a failure.
+    #[serde(default)]
+    pub(crate) next_retry_at: Option<DateTime<Utc>>,
+    /// When the topic was found unwatched.
+    #[serde(default)]
+    pub(crate) pending_deletion_since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub(crate) overrides: TaskOverrides,
+}
+
+/// Changes to how a single topic is synced, set with `tasks set`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub(crate) struct TaskOverrides {
+    /// Task isn't deleted when its topic is not watched anymore, except in wipeout mode.
+    #[serde(default)]
+    pub(crate) pinned: bool,
+    /// Topic is downloaded once, its new versions are ignored.
+    #[serde(default)]
+    pub(crate) skip_updates: bool,
+    /// Download path used instead of the category's path template.
+    #[serde(default)]
+    pub(crate) directory: Option<String>,
+    /// Topic is tracked without downloading it.
+    #[serde(default)]
+    pub(crate) track_only: bool,
 }
 
-impl TaskDb {
-    pub(crate) fn create(path: &str) -> StorageResult<Self> {
-        let db = sled::open(path)?;
+/// Runs [`test_suite`] against the storage created by the expression, once per test.
+#[cfg(test)]
+macro_rules! task_db_tests {
+    ($create:expr) => {
+        #[test]
+        fn test_replaces_tasks() {
+            $crate::task_db::test_suite::replaces_tasks(&$create);
+        }
+
+        #[test]
+        fn test_updates_single_task() {
+            $crate::task_db::test_suite::updates_single_task(&$create);
+        }
+
+        #[test]
+        fn test_saves_all_tasks_at_once() {
+            $crate::task_db::test_suite::saves_all_tasks_at_once(&$create);
+        }
+
+        #[test]
+        fn test_keeps_latest_reports() {
+            $crate::task_db::test_suite::keeps_latest_reports(&$create);
+        }
+
+        #[test]
+        fn test_round_trips_reports() {
+            $crate::task_db::test_suite::round_trips_reports(&$create);
+        }
 
-        Ok(Self { db })
+        #[test]
+        fn test_reads_events_by_time_and_topic() {
+            $crate::task_db::test_suite::reads_events_by_time_and_topic(&$create);
+        }
+    };
+}
+
+#[cfg(test)]
+pub(crate) use task_db_tests;
+
+/// Tests every backend has to pass, run with [`task_db_tests`].
+#[cfg(test)]
+pub(crate) mod test_suite {
+    use super::*;
+
+    use crate::event_log::EventKind;
+    use crate::report::TopicResult;
+
+    pub(crate) fn task(topic_id: &str) -> Task {
+        Task {
+            topic_id: topic_id.to_string(),
+            topic_title: format!("Topic {}", topic_id),
+            previous_title: None,
+            topic_download_registered_at: "2024-01-01".to_string(),
+            transmission_torrent_id: Some(TorrentId::Id(1)),
+            torrent_hash: None,
+            category: None,
+            size: None,
+            poster_url: None,
+            task_status: TaskStatus::Added,
+            last_error: None,
+            attempts: 0,
+            next_retry_at: None,
+            pending_deletion_since: None,
+            overrides: TaskOverrides::default(),
+        }
     }
 
-    #[tracing::instrument(err, skip(self))]
-    pub(crate) fn get_tasks(&self) -> StorageResult<Vec<Task>> {
-        let raw = self.db.get(TASKS_KEY)?;
-        let tasks = match raw {
-            Some(raw) => serde_json::from_slice(raw.as_ref()).unwrap_or_default(),
-            None => vec![],
-        };
+    pub(crate) fn topic_ids(task_db: &dyn TaskDb) -> Vec<String> {
+        task_db
+            .get_tasks()
+            .unwrap()
+            .into_iter()
+            .map(|task| task.topic_id)
+            .collect()
+    }
 
-        Ok(tasks)
+    fn at(hour: u32) -> DateTime<Utc> {
+        "2024-06-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+            + chrono::Duration::hours(hour.into())
     }
 
-    #[tracing::instrument(err, skip(self))]
-    pub(crate) fn delete_task_by_topic_id(&self, topic_id: &str) -> StorageResult<()> {
-        let tasks = self.get_tasks()?;
-        self.save_tasks(
-            &tasks
-                .into_iter()
-                .filter(|t| &t.topic_id != topic_id)
-                .collect(),
-        )
+    pub(crate) fn replaces_tasks(task_db: &dyn TaskDb) {
+        task_db.replace_task(task("2")).unwrap();
+        task_db.replace_task(task("1")).unwrap();
+        task_db
+            .replace_task(Task {
+                overrides: TaskOverrides {
+                    pinned: true,
+                    ..TaskOverrides::default()
+                },
+                ..task("2")
+            })
+            .unwrap();
+
+        assert_eq!(topic_ids(task_db), vec!["1", "2"]);
+        assert!(
+            task_db
+                .get_task_by_topic_id("2")
+                .unwrap()
+                .unwrap()
+                .overrides
+                .pinned
+        );
+        assert!(task_db.get_task_by_topic_id("3").unwrap().is_none());
     }
 
-    #[tracing::instrument(err, skip(self))]
-    pub(crate) fn mark_task_as_finished_by_topic_id(&self, topic_id: &str) -> StorageResult<()> {
-        let mut tasks = self.get_tasks()?;
+    pub(crate) fn updates_single_task(task_db: &dyn TaskDb) {
+        task_db
+            .replace_task(Task {
+                last_error: Some("Timeout".to_string()),
+                attempts: 2,
+                ..task("1")
+            })
+            .unwrap();
+        task_db.replace_task(task("2")).unwrap();
+        task_db.replace_task(task("3")).unwrap();
+
+        task_db.mark_task_as_finished_by_topic_id("1").unwrap();
+        task_db.mark_task_as_finished_by_topic_id("4").unwrap();
+        task_db
+            .mark_task_as_pending_deletion_by_topic_id("3", at(1))
+            .unwrap();
+        task_db.delete_task_by_topic_id("2").unwrap();
+
+        let task = task_db.get_task_by_topic_id("1").unwrap().unwrap();
+        assert!(matches!(task.task_status, TaskStatus::Finished));
+        assert_eq!(task.last_error, None);
+        assert_eq!(task.attempts, 0);
+        let task = task_db.get_task_by_topic_id("3").unwrap().unwrap();
+        assert!(matches!(task.task_status, TaskStatus::PendingDeletion));
+        assert_eq!(task.pending_deletion_since, Some(at(1)));
+        assert_eq!(topic_ids(task_db), vec!["1", "3"]);
 
-        tasks
-            .iter_mut()
-            .filter(|task| &task.topic_id == topic_id)
-            .for_each(|task| {
-                task.task_status = TaskStatus::Finished;
-            });
+        task_db
+            .restore_task_by_topic_id("3", TaskStatus::Finished)
+            .unwrap();
 
-        self.save_tasks(&tasks)
+        let task = task_db.get_task_by_topic_id("3").unwrap().unwrap();
+        assert!(matches!(task.task_status, TaskStatus::Finished));
+        assert_eq!(task.pending_deletion_since, None);
     }
 
-    #[tracing::instrument(err, skip(self))]
-    pub(crate) fn add_task(&self, task: Task) -> StorageResult<()> {
-        let mut tasks = self.get_tasks()?;
-        tasks.push(task);
-        self.save_tasks(&tasks)
+    pub(crate) fn saves_all_tasks_at_once(task_db: &dyn TaskDb) {
+        task_db.replace_task(task("1")).unwrap();
+
+        task_db.save_tasks(&[task("2"), task("3")]).unwrap();
+
+        assert_eq!(topic_ids(task_db), vec!["2", "3"]);
     }
 
-    #[tracing::instrument(err, skip(self))]
-    fn save_tasks(&self, tasks: &Vec<Task>) -> StorageResult<()> {
-        let vec = serde_json::to_vec(tasks).unwrap();
-        let _ = self.db.insert(TASKS_KEY, vec)?;
-        Ok(())
+    pub(crate) fn keeps_latest_reports(task_db: &dyn TaskDb) {
+        for hour in 0..(MAX_REPORTS as u32 + 5) {
+            let mut report = SyncReport::start();
+            report.started_at = at(hour);
+            task_db.save_report(&report).unwrap();
+        }
+
+        let reports = task_db.get_reports(usize::MAX).unwrap();
+
+        assert_eq!(reports.len(), MAX_REPORTS);
+        assert_eq!(reports[0].started_at, at(MAX_REPORTS as u32 + 4));
+        assert_eq!(task_db.get_reports(2).unwrap().len(), 2);
+    }
+
+    pub(crate) fn round_trips_reports(task_db: &dyn TaskDb) {
+        let report = SyncReport {
+            action_counts: [("add".to_string(), 2), ("delete".to_string(), 1)].into(),
+            blocked_deletions: 1,
+            blocked_topic_ids: vec!["3".to_string()],
+            topics: vec![
+                TopicResult {
+                    topic_id: "1".to_string(),
+                    title: "Topic 1".to_string(),
+                    action: "add".to_string(),
+                    error: None,
+                },
+                TopicResult {
+                    topic_id: "2".to_string(),
+                    title: "Topic 2".to_string(),
+                    action: "add".to_string(),
+                    error: Some("404".to_string()),
+                },
+            ],
+            toloka_time_ms: 1200,
+            download_client_time_ms: 300,
+            interrupted: true,
+            error: Some("Invalid login or password".to_string()),
+            ..SyncReport::start()
+        };
+
+        task_db.save_report(&report).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(task_db.get_reports(1).unwrap()).unwrap(),
+            serde_json::to_value([report]).unwrap()
+        );
+    }
+
+    pub(crate) fn reads_events_by_time_and_topic(task_db: &dyn TaskDb) {
+        let event = |hour: u32, kind: EventKind, topic_id: &str| Event {
+            at: at(hour),
+            ..Event::new(kind, topic_id, &format!("Topic {}", topic_id))
+        };
+
+        task_db
+            .append_event(&event(3, EventKind::Deleted, "1"))
+            .unwrap();
+        task_db
+            .append_event(&event(1, EventKind::Added, "1"))
+            .unwrap();
+        task_db
+            .append_event(&event(2, EventKind::Added, "2"))
+            .unwrap();
+        task_db
+            .append_event(&event(2, EventKind::Updated, "2"))
+            .unwrap();
+
+        let events = |filter: EventFilter| {
+            task_db
+                .get_events(&filter)
+                .unwrap()
+                .into_iter()
+                .map(|event| (event.at, event.kind, event.topic_id))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            events(EventFilter::default()),
+            vec![
+                (at(1), EventKind::Added, "1".to_string()),
+                (at(2), EventKind::Added, "2".to_string()),
+                (at(2), EventKind::Updated, "2".to_string()),
+                (at(3), EventKind::Deleted, "1".to_string()),
+            ]
+        );
+        assert_eq!(
+            events(EventFilter {
+                since: Some(at(2)),
+                until: Some(at(2)),
+                ..EventFilter::default()
+            }),
+            vec![
+                (at(2), EventKind::Added, "2".to_string()),
+                (at(2), EventKind::Updated, "2".to_string()),
+            ]
+        );
+        assert_eq!(
+            events(EventFilter {
+                topic_id: Some("1".to_string()),
+                since: Some(at(2)),
+                ..EventFilter::default()
+            }),
+            vec![(at(3), EventKind::Deleted, "1".to_string())]
+        );
+        assert_eq!(
+            events(EventFilter {
+                kind: Some(EventKind::Added),
+                ..EventFilter::default()
+            }),
+            vec![
+                (at(1), EventKind::Added, "1".to_string()),
+                (at(2), EventKind::Added, "2".to_string()),
+            ]
+        );
     }
 }
@@ -0,0 +1,301 @@
+//! Metadata parsed from toloka topic titles, which look like
+//! `Назва / Original Name (Сезон 1, серії 1-8 з 10) (2023) WEB-DL 1080p`.
+
+/// Words that precede an episode range in titles.
+const EPISODE_WORDS: [&str; 8] = [
+    "серії",
+    "серія",
+    "серій",
+    "епізоди",
+    "епізод",
+    "episodes",
+    "episode",
+    "eps",
+];
+
+/// Words that describe quality of a release.
+const QUALITY_WORDS: [&str; 20] = [
+    "2160p", "1080p", "1080i", "720p", "576p", "480p", "4k", "uhd", "hdr", "web-dl", "webdl",
+    "webrip", "bdrip", "blu-ray", "bluray", "remux", "hdrip", "hdtv", "dvdrip", "hevc",
+];
+
+/// First name of the topic, before the original one.
+pub(crate) fn short_title(title: &str) -> String {
+    let end = title
+        .find(" / ")
+        .or_else(|| title.find(['(', '[']))
+        .unwrap_or(title.len());
+
+    title[..end].trim().to_string()
+}
+
+/// First standalone four-digit number that looks like a year.
+pub(crate) fn parse_year(title: &str) -> Option<u16> {
+    title
+        .split(|char: char| !char.is_ascii_digit())
+        .filter(|number| number.len() == 4)
+        .filter_map(|number| number.parse::<u16>().ok())
+        .find(|year| (1900..=2099).contains(year))
+}
+
+/// Season number from `Сезон 2`, `2 сезон`, `Season 2` or `S02E01`.
+pub(crate) fn parse_season(title: &str) -> Option<u32> {
+    let words = title
+        .split(|char: char| !char.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>();
+
+    for (index, word) in words.iter().enumerate() {
+        if word == "сезон" || word == "season" {
+            let after = words.get(index + 1).and_then(|word| word.parse().ok());
+            let before = index
+                .checked_sub(1)
+                .and_then(|index| words[index].parse().ok());
+
+            if let Some(season) = after.or(before) {
+                return Some(season);
+            }
+        }
+
+        if let Some(season) = parse_episode_code(word) {
+            return Some(season);
+        }
+    }
+
+    None
+}
+
+/// Season from `s02` or `s02e01`.
+fn parse_episode_code(word: &str) -> Option<u32> {
+    let rest = word.strip_prefix('s')?;
+    let digits = rest
+        .chars()
+        .take_while(|char| char.is_ascii_digit())
+        .collect::<String>();
+    let suffix = &rest[digits.len()..];
+
+    if digits.is_empty() || !(suffix.is_empty() || suffix.starts_with('e')) {
+        return None;
+    }
+
+    digits.parse().ok()
+}
+
+/// Range of episodes in the topic, inclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Episodes {
+    pub(crate) from: u32,
+    pub(crate) to: u32,
+}
+
+/// Episodes from `серії 1-4 з 8`, `серія 5`, `Episodes 1-4` or `S02E01-E04`.
+pub(crate) fn parse_episodes(title: &str) -> Option<Episodes> {
+    let title = title.to_lowercase();
+
+    for word in EPISODE_WORDS {
+        let Some(index) = find_word(&title, word) else {
+            continue;
+        };
+        let rest = title[index + word.len()..].trim_start_matches([' ', ':']);
+
+        if let Some(episodes) = parse_range(rest) {
+            return Some(episodes);
+        }
+    }
+
+    title
+        .split(|char: char| !char.is_alphanumeric() && char != '-')
+        .find_map(|word| {
+            let rest = word.strip_prefix('s')?;
+            let index = rest.find('e')?;
+            rest[..index].parse::<u32>().ok()?;
+
+            parse_range(&rest[index + 1..].replace("-e", "-"))
+        })
+}
+
+/// Position of `word` in `text`, not being a part of another word.
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    text.match_indices(word)
+        .map(|(index, _)| index)
+        .find(|&index| {
+            let before = text[..index].chars().next_back();
+            let after = text[index + word.len()..].chars().next();
+
+            !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+        })
+}
+
+/// Range like `1-4` or a single number at the start of `text`.
+fn parse_range(text: &str) -> Option<Episodes> {
+    let number = |text: &str| -> Option<(u32, usize)> {
+        let digits = text.chars().take_while(char::is_ascii_digit).count();
+        Some((text[..digits].parse().ok()?, digits))
+    };
+
+    let (from, length) = number(text)?;
+    let to = text[length..]
+        .trim_start()
+        .strip_prefix('-')
+        .and_then(|rest| number(rest.trim_start()))
+        .map_or(from, |(to, _)| to);
+
+    Some(Episodes { from, to })
+}
+
+/// Quality words of the title, like `WEB-DL 1080p`, as they're written in it.
+pub(crate) fn parse_quality(title: &str) -> Vec<String> {
+    title
+        .split(|char: char| char.is_whitespace() || "()[],/|".contains(char))
+        .filter(|word| QUALITY_WORDS.contains(&word.to_lowercase().as_str()))
+        .map(String::from)
+        .collect()
+}
+
+/// Describes what changed between the previous and the new title of a topic,
+/// e.g. `Дім Дракона S2: episodes 5-6 added`.
+pub(crate) fn describe_update(previous_title: &str, title: &str) -> String {
+    let mut changes = vec![];
+
+    if let (Some(previous), Some(current)) = (parse_episodes(previous_title), parse_episodes(title))
+    {
+        if current.to > previous.to {
+            let from = previous.to.max(current.from.saturating_sub(1)) + 1;
+
+            changes.push(match from == current.to {
+                true => format!("episode {} added", from),
+                false => format!("episodes {}-{} added", from, current.to),
+            });
+        }
+    }
+
+    let previous_quality = parse_quality(previous_title);
+    let quality = parse_quality(title);
+    let normalize = |quality: &[String]| {
+        let mut quality = quality
+            .iter()
+            .map(|word| word.to_lowercase())
+            .collect::<Vec<_>>();
+        quality.sort();
+        quality
+    };
+
+    if !previous_quality.is_empty()
+        && !quality.is_empty()
+        && normalize(&previous_quality) != normalize(&quality)
+    {
+        changes.push(format!(
+            "quality {} → {}",
+            previous_quality.join(" "),
+            quality.join(" ")
+        ));
+    }
+
+    if changes.is_empty() {
+        changes.push("updated".to_string());
+    }
+
+    let name = match parse_season(title) {
+        Some(season) => format!("{} S{}", short_title(title), season),
+        None => short_title(title),
+    };
+
+    format!("{}: {}", name, changes.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_year() {
+        assert_eq!(parse_year("Дюна (2021) 1080p"), Some(2021));
+        assert_eq!(parse_year("Дюна 1080p 12345"), None);
+    }
+
+    #[test]
+    fn test_parses_season() {
+        assert_eq!(parse_season("Тед Лассо (Сезон 2, серії 1-12)"), Some(2));
+        assert_eq!(parse_season("Тед Лассо (3 сезон)"), Some(3));
+        assert_eq!(parse_season("Ted Lasso Season 1"), Some(1));
+        assert_eq!(parse_season("Ted.Lasso.S04E01.1080p"), Some(4));
+        assert_eq!(parse_season("Дюна (2021) 1080p"), None);
+    }
+
+    #[test]
+    fn test_parses_short_title() {
+        assert_eq!(
+            short_title("Дім Дракона / House of the Dragon (Сезон 2) (2024)"),
+            "Дім Дракона"
+        );
+        assert_eq!(short_title("Дюна (2021) 1080p"), "Дюна");
+    }
+
+    #[test]
+    fn test_parses_episodes() {
+        assert_eq!(
+            parse_episodes("Дім Дракона (Сезон 2, серії 1-4 з 8) (2024)"),
+            Some(Episodes { from: 1, to: 4 })
+        );
+        assert_eq!(
+            parse_episodes("Дім Дракона (Сезон 2, серія 5)"),
+            Some(Episodes { from: 5, to: 5 })
+        );
+        assert_eq!(
+            parse_episodes("House of the Dragon S02E01-E04 1080p"),
+            Some(Episodes { from: 1, to: 4 })
+        );
+        assert_eq!(
+            parse_episodes("Серіал (Сезон 2, серії 1 - 6)"),
+            Some(Episodes { from: 1, to: 6 })
+        );
+        assert_eq!(parse_episodes("Дюна (2021) 1080p"), None);
+    }
+
+    #[test]
+    fn test_parses_quality() {
+        assert_eq!(
+            parse_quality("Дюна (2021) WEB-DL 1080p Ukr/Eng"),
+            vec!["WEB-DL", "1080p"]
+        );
+        assert!(parse_quality("Дюна (2021)").is_empty());
+    }
+
+    #[test]
+    fn test_describes_new_episodes() {
+        assert_eq!(
+            describe_update(
+                "Дім Дракона / House of the Dragon (Сезон 2, серії 1-4 з 8) (2024) WEB-DL 1080p",
+                "Дім Дракона / House of the Dragon (Сезон 2, серії 1-6 з 8) (2024) WEB-DL 1080p"
+            ),
+            "Дім Дракона S2: episodes 5-6 added"
+        );
+        assert_eq!(
+            describe_update(
+                "Дім Дракона (Сезон 2, серії 1-4)",
+                "Дім Дракона (Сезон 2, серії 1-5)"
+            ),
+            "Дім Дракона S2: episode 5 added"
+        );
+    }
+
+    #[test]
+    fn test_describes_quality_change() {
+        assert_eq!(
+            describe_update(
+                "Дюна / Dune (2021) WEB-DL 720p",
+                "Дюна / Dune (2021) BDRip 1080p"
+            ),
+            "Дюна: quality WEB-DL 720p → BDRip 1080p"
+        );
+    }
+
+    #[test]
+    fn test_describes_unknown_change() {
+        assert_eq!(
+            describe_update("Дюна / Dune (2021)", "Дюна / Dune (2021) Ukr/Eng"),
+            "Дюна: updated"
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use torrent_bot_clients::telegram::html;
+
+/// Older events are dropped; the full log stays in the runner's storage.
+const MAX_EVENTS: usize = 1000;
+
+/// Event as sent by the runner. Only fields events are filtered by are typed.
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Event {
+    at: DateTime<Utc>,
+    topic_id: String,
+    #[serde(flatten)]
+    fields: Map<String, Value>,
+}
+
+impl Event {
+    fn field(&self, name: &str) -> &str {
+        self.fields.get(name).and_then(Value::as_str).unwrap_or("")
+    }
+}
+
+/// Recent events of the runner, oldest first.
+///
+/// They're kept in memory only, so they're lost when the server restarts,
+/// until the runner sends new ones. The runner's storage keeps all of them.
+#[derive(Default)]
+pub(crate) struct Events {
+    events: Mutex<VecDeque<Event>>,
+}
+
+impl Events {
+    /// Events at or after the time, oldest first.
+    pub(crate) fn since(&self, since: DateTime<Utc>) -> Vec<Event> {
+        let events = self.events.lock().unwrap();
+
+        events
+            .iter()
+            .filter(|event| event.at >= since)
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EventsQuery {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    topic_id: Option<String>,
+}
+
+impl EventsQuery {
+    fn matches(&self, event: &Event) -> bool {
+        self.since.is_none_or(|since| event.at >= since)
+            && self.until.is_none_or(|until| event.at <= until)
+            && self
+                .topic_id
+                .as_ref()
+                .is_none_or(|topic_id| &event.topic_id == topic_id)
+    }
+}
+
+pub(crate) async fn add_events(
+    json: web::Json<Vec<Event>>,
+    events: web::Data<Events>,
+) -> impl Responder {
+    let mut events = events.events.lock().unwrap();
+
+    events.extend(json.into_inner());
+    while events.len() > MAX_EVENTS {
+        events.pop_front();
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+/// Returns events in the time range and of the topic, if given.
+pub(crate) async fn get_events(
+    query: web::Query<EventsQuery>,
+    events: web::Data<Events>,
+) -> impl Responder {
+    let events = events.events.lock().unwrap();
+    let matching = events
+        .iter()
+        .filter(|event| query.matches(event))
+        .collect::<Vec<_>>();
+
+    HttpResponse::Ok().json(matching)
+}
+
+/// Parses how long ago, in minutes, hours or days (`30m`, `12h`, `7d`).
+pub(crate) fn parse_period(value: &str) -> Option<Duration> {
+    let unit_at = value.len().checked_sub(1)?;
+    let (amount, unit) = value.split_at(unit_at);
+    let amount = amount.parse::<i64>().ok().filter(|amount| *amount >= 0)?;
+
+    match unit {
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        _ => None,
+    }
+}
+
+fn render_event(event: &Event) -> String {
+    let line = format!(
+        "{} {}: {}",
+        event.at.format("%d.%m %H:%M"),
+        event.field("kind"),
+        html::escape(event.field("title"))
+    );
+
+    match event.fields.get("error").and_then(Value::as_str) {
+        Some(error) => format!("{} ({})", line, html::escape(error)),
+        None => line,
+    }
+}
+
+/// Renders events as a message in Telegram's HTML style, a line per event, times in UTC.
+/// The oldest events are left out when the message would be longer than `limit` characters.
+pub(crate) fn render_events(events: &[Event], limit: usize) -> String {
+    let mut lines = vec![];
+    let mut length = 0;
+
+    for (index, event) in events.iter().enumerate().rev() {
+        let line = render_event(event);
+        length += line.chars().count() + 1;
+
+        // Room is left for the line about the left out events.
+        if length > limit.saturating_sub(40) {
+            lines.push(format!("…and {} earlier", index + 1));
+            break;
+        }
+
+        lines.push(line);
+    }
+
+    lines.reverse();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn event(at: &str, kind: &str, title: &str) -> Event {
+        serde_json::from_value(json!({
+            "at": at,
+            "kind": kind,
+            "topic_id": "t1",
+            "title": title,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parses_period() {
+        assert_eq!(parse_period("30m"), Some(Duration::minutes(30)));
+        assert_eq!(parse_period("12h"), Some(Duration::hours(12)));
+        assert_eq!(parse_period("7d"), Some(Duration::days(7)));
+        assert_eq!(parse_period(""), None);
+        assert_eq!(parse_period("12"), None);
+        assert_eq!(parse_period("-1h"), None);
+        assert_eq!(parse_period("yesterday"), None);
+    }
+
+    #[test]
+    fn test_renders_events() {
+        let events = vec![
+            event("2024-06-01T22:15:00Z", "added", "Дюна <2021>"),
+            event("2024-06-02T03:40:00Z", "deleted", "Тед Лассо"),
+        ];
+
+        assert_eq!(
+            render_events(&events, 4096),
+            "01.06 22:15 added: Дюна &lt;2021&gt;\n02.06 03:40 deleted: Тед Лассо"
+        );
+    }
+
+    #[test]
+    fn test_leaves_out_oldest_events() {
+        let events = (0..100)
+            .map(|_| event("2024-06-01T22:15:00Z", "added", "Topic"))
+            .collect::<Vec<_>>();
+
+        let message = render_events(&events, 500);
+
+        assert!(message.chars().count() <= 500);
+        assert!(message.starts_with("…and "));
+        assert!(message.ends_with("01.06 22:15 added: Topic"));
+    }
+}
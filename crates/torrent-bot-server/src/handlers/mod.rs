@@ -1,2 +1,5 @@
+pub(crate) mod events;
+pub(crate) mod notifications;
 pub(crate) mod readiness_check;
+pub(crate) mod sync_reports;
 pub(crate) mod telegram_bot;
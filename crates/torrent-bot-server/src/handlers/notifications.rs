@@ -0,0 +1,640 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::Deserialize;
+use tracing::info;
+
+use torrent_bot_clients::telegram::{html, ActionButton, TelegramBotClient, MAX_MESSAGE_LENGTH};
+
+const GIB: f64 = (1024 * 1024 * 1024) as f64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum NotificationKind {
+    Added,
+    Updated,
+    Downloaded,
+    Deleted,
+    PendingDeletion,
+    Restored,
+    Tracked,
+    FilesSkipped,
+    NotEnoughSpace,
+    Failed,
+    DeletionBlocked,
+}
+
+impl NotificationKind {
+    /// Order of the sections in a digest.
+    const ALL: [Self; 4] = [Self::Added, Self::Updated, Self::Downloaded, Self::Deleted];
+
+    /// Order of the alerts in the last section of a digest, which has the kind on every line.
+    const OTHER: [Self; 6] = [
+        Self::Failed,
+        Self::NotEnoughSpace,
+        Self::PendingDeletion,
+        Self::Restored,
+        Self::Tracked,
+        Self::FilesSkipped,
+    ];
+
+    fn heading(self) -> &'static str {
+        match self {
+            Self::Added => "Added",
+            Self::Updated => "Updated",
+            Self::Downloaded => "Downloaded",
+            Self::Deleted => "Deleted",
+            Self::PendingDeletion => "Not watched anymore",
+            Self::Restored => "Restored",
+            Self::Tracked => "Updated, not downloaded",
+            Self::FilesSkipped => "Skipped files",
+            Self::NotEnoughSpace => "Not enough free space",
+            Self::Failed => "Failed",
+            Self::DeletionBlocked => "Deletion blocked",
+        }
+    }
+}
+
+/// Notification about a topic, as sent by the runner.
+#[derive(Debug, Deserialize)]
+pub(crate) struct NotificationJson {
+    kind: NotificationKind,
+    topic_id: String,
+    title: String,
+    #[serde(default)]
+    category: Option<String>,
+    /// Size of the torrent in bytes.
+    #[serde(default)]
+    size: Option<u64>,
+    link: String,
+    /// What changed in an updated topic.
+    #[serde(default)]
+    summary: Option<String>,
+    /// Poster image of the topic, sent as a photo with the message.
+    #[serde(default)]
+    poster_url: Option<String>,
+    /// Files left out of the download.
+    #[serde(default)]
+    skipped_files: Vec<String>,
+    /// Bytes the torrent needs in the download directory.
+    #[serde(default)]
+    required_space: Option<u64>,
+    /// Bytes available in the download directory.
+    #[serde(default)]
+    available_space: Option<u64>,
+    /// Failed attempts of a failed topic.
+    #[serde(default)]
+    attempts: Option<u32>,
+    /// Last error of a failed topic.
+    #[serde(default)]
+    error: Option<String>,
+    /// Hours until files of a topic pending deletion are deleted.
+    #[serde(default)]
+    deleted_in_hours: Option<i64>,
+}
+
+/// Category and size of the topic, when they're known.
+fn details(notification: &NotificationJson) -> Vec<String> {
+    [
+        notification.category.as_deref().map(html::escape),
+        notification
+            .size
+            .map(|size| format!("{:.2} GiB", size as f64 / GIB)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Title of the topic in bold, linking to the topic.
+fn title(notification: &NotificationJson) -> String {
+    html::bold(&html::link(&notification.link, &notification.title))
+}
+
+/// What went wrong or what happens next, for kinds that aren't only about the topic itself.
+fn explanation(notification: &NotificationJson) -> Option<String> {
+    match notification.kind {
+        NotificationKind::PendingDeletion => notification
+            .deleted_in_hours
+            .map(|hours| format!("Its files will be deleted in {} hours.", hours)),
+        NotificationKind::FilesSkipped => Some(
+            notification
+                .skipped_files
+                .iter()
+                .map(|file| html::escape(file))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        NotificationKind::NotEnoughSpace => Some(format!(
+            "{:.2} GiB required, {:.2} GiB available. Will retry on next run.",
+            notification.required_space.unwrap_or_default() as f64 / GIB,
+            notification.available_space.unwrap_or_default() as f64 / GIB,
+        )),
+        NotificationKind::Failed => Some(format!(
+            "Gave up after {} attempts. Last error: {}",
+            notification.attempts.unwrap_or_default(),
+            html::escape(notification.error.as_deref().unwrap_or_default())
+        )),
+        _ => None,
+    }
+}
+
+/// Renders the notification as a message in Telegram's HTML style.
+fn render(notification: &NotificationJson) -> String {
+    let heading = notification.kind.heading();
+    let headline = match (notification.kind, &notification.summary) {
+        (NotificationKind::Updated | NotificationKind::Tracked, Some(summary)) => format!(
+            "{}: {}\n{}",
+            heading,
+            html::escape(summary),
+            title(notification)
+        ),
+        _ => format!("{}: {}", heading, title(notification)),
+    };
+
+    let details = details(notification);
+    let details = (!details.is_empty()).then(|| details.join(", "));
+
+    [Some(headline), details, explanation(notification)]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Button that watches the topic again, which cancels its deletion.
+fn undo_button(notification: &NotificationJson) -> ActionButton {
+    ActionButton {
+        text: "Undo".to_string(),
+        action: format!("add_{}", notification.topic_id.trim_start_matches('t')),
+    }
+}
+
+/// Kind of an alert in the "Other" section of the digest, which isn't told by its heading.
+fn digest_label(notification: &NotificationJson) -> String {
+    match NotificationKind::OTHER.contains(&notification.kind) {
+        true => format!("{}: ", notification.kind.heading()),
+        false => String::new(),
+    }
+}
+
+/// Undo button of a topic pending deletion in the digest, which tells the topics apart.
+fn digest_undo_button(notification: &NotificationJson) -> ActionButton {
+    ActionButton {
+        text: format!("Undo: {}", notification.title),
+        ..undo_button(notification)
+    }
+}
+
+/// Renders a single line of the digest section.
+fn render_digest_item(notification: &NotificationJson) -> String {
+    let mut details = details(notification);
+    if let Some(summary) = &notification.summary {
+        details.insert(0, html::escape(summary));
+    }
+    if NotificationKind::OTHER.contains(&notification.kind) {
+        details
+            .extend(explanation(notification).map(|explanation| explanation.replace('\n', "; ")));
+    }
+
+    let label = digest_label(notification);
+    if details.is_empty() {
+        format!("• {}{}", label, title(notification))
+    } else {
+        format!(
+            "• {}{} — {}",
+            label,
+            title(notification),
+            details.join(", ")
+        )
+    }
+}
+
+/// Renders a line of the digest that's at most `limit` characters long. A line that's
+/// longer is left without details, and its title is shortened as little as possible.
+/// The title is cut before it's escaped, so the HTML stays valid.
+fn render_fitting_digest_item(notification: &NotificationJson, limit: usize) -> String {
+    let item = render_digest_item(notification);
+    if item.chars().count() <= limit {
+        return item;
+    }
+
+    let shortened = |length: usize| {
+        let title = notification.title.chars().take(length).collect::<String>();
+        format!(
+            "• {}{}",
+            digest_label(notification),
+            html::bold(&html::link(&notification.link, &format!("{}…", title)))
+        )
+    };
+
+    // Escaping makes some characters longer, so the longest title that fits is searched for.
+    let (mut fits, mut too_long) = (0, notification.title.chars().count());
+    while fits + 1 < too_long {
+        let length = (fits + too_long) / 2;
+
+        match shortened(length).chars().count() <= limit {
+            true => fits = length,
+            false => too_long = length,
+        }
+    }
+
+    shortened(fits)
+}
+
+/// Renders notifications of a sync run as sections per kind, followed by an "Other" section
+/// with the alerts, split into messages of at most `limit` characters. A section that doesn't fit into one message is
+/// continued in the next one under the same heading. Lines are never split,
+/// a line too long for a message on its own is shortened instead.
+fn render_digest(notifications: &[NotificationJson], limit: usize) -> Vec<String> {
+    let mut messages = vec![];
+    let mut message = String::new();
+
+    let sections = NotificationKind::ALL
+        .iter()
+        .map(|kind| (kind.heading(), vec![*kind]))
+        .chain([("Other", NotificationKind::OTHER.to_vec())]);
+
+    for (heading, kinds) in sections {
+        let section = kinds
+            .iter()
+            .flat_map(|kind| {
+                notifications
+                    .iter()
+                    .filter(move |notification| notification.kind == *kind)
+            })
+            .collect::<Vec<_>>();
+
+        if section.is_empty() {
+            continue;
+        }
+
+        let heading = html::bold(&format!("{} ({})", heading, section.len()));
+        // Room for the heading the item may be continued under.
+        let item_limit = limit.saturating_sub(heading.chars().count() + 1);
+        let mut has_heading = false;
+
+        for notification in section {
+            let item = render_fitting_digest_item(notification, item_limit);
+            let next = match (message.is_empty(), has_heading) {
+                (true, _) => format!("{}\n{}", heading, item),
+                (false, false) => format!("{}\n\n{}\n{}", message, heading, item),
+                (false, true) => format!("{}\n{}", message, item),
+            };
+
+            if next.chars().count() > limit && !message.is_empty() {
+                messages.push(std::mem::take(&mut message));
+                message = format!("{}\n{}", heading, item);
+            } else {
+                message = next;
+            }
+            has_heading = true;
+        }
+    }
+
+    if !message.is_empty() {
+        messages.push(message);
+    }
+
+    messages
+}
+
+pub(crate) async fn send_notification(
+    json: web::Json<NotificationJson>,
+    telegram_bot: web::Data<TelegramBotClient>,
+) -> impl Responder {
+    info!(topic_id = json.topic_id, kind = ?json.kind, "Sending notification");
+
+    let message = render(&json);
+    match (json.kind, &json.poster_url) {
+        (NotificationKind::PendingDeletion, _) => {
+            telegram_bot
+                .send_html_message_with_action_buttons(&message, vec![undo_button(&json)])
+                .await
+        }
+        (_, Some(poster_url)) => telegram_bot.send_photo(poster_url, &message).await,
+        (_, None) => telegram_bot.send_html_message(&message).await,
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+pub(crate) async fn send_digest(
+    json: web::Json<Vec<NotificationJson>>,
+    telegram_bot: web::Data<TelegramBotClient>,
+) -> impl Responder {
+    info!(count = json.len(), "Sending notification digest");
+
+    for message in render_digest(&json, MAX_MESSAGE_LENGTH) {
+        telegram_bot.send_html_message(&message).await;
+    }
+
+    // Digest lines can't have buttons, so deletions are undone from a message of their own.
+    let undo_buttons = json
+        .iter()
+        .filter(|notification| notification.kind == NotificationKind::PendingDeletion)
+        .map(digest_undo_button)
+        .collect::<Vec<_>>();
+    if !undo_buttons.is_empty() {
+        telegram_bot
+            .send_html_message_with_action_buttons(
+                "Undo to watch a topic again and keep its files:",
+                undo_buttons,
+            )
+            .await;
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+/// Renders topics whose deletion was blocked as a list under an explanation, split into
+/// messages of at most `limit` characters like the digest.
+fn render_deletion_blocked(notifications: &[NotificationJson], limit: usize) -> Vec<String> {
+    let mut messages = vec![];
+    let mut message = format!(
+        "Deletion of {} topics was blocked, the watched list may be broken. Run {} to delete them:",
+        notifications.len(),
+        html::code_inline("sync --allow-mass-deletion")
+    );
+
+    for notification in notifications.iter() {
+        let item = render_fitting_digest_item(notification, limit);
+        let next = format!("{}\n{}", message, item);
+
+        if next.chars().count() > limit {
+            messages.push(std::mem::replace(&mut message, item));
+        } else {
+            message = next;
+        }
+    }
+
+    messages.push(message);
+    messages
+}
+
+pub(crate) async fn send_deletion_blocked(
+    json: web::Json<Vec<NotificationJson>>,
+    telegram_bot: web::Data<TelegramBotClient>,
+) -> impl Responder {
+    info!(count = json.len(), "Sending blocked deletions");
+
+    for message in render_deletion_blocked(&json, MAX_MESSAGE_LENGTH) {
+        telegram_bot.send_html_message(&message).await;
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(json: serde_json::Value) -> NotificationJson {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_renders_notifications() {
+        let added = notification(serde_json::json!({
+            "kind": "added",
+            "topic_id": "t679577",
+            "title": "Тед Лассо (Сезон 2) WEB-DL 1080p",
+            "category": "Series",
+            "size": 3 * 1024 * 1024 * 1024_u64 / 2,
+            "link": "https://toloka.to/t679577",
+        }));
+        let updated = notification(serde_json::json!({
+            "kind": "updated",
+            "topic_id": "t679577",
+            "title": "Тед Лассо (Сезон 2) WEB-DL 1080p",
+            "link": "https://toloka.to/t679577",
+            "summary": "Тед Лассо S2: episodes 5-6 added",
+        }));
+        let escaped = notification(serde_json::json!({
+            "kind": "deleted",
+            "topic_id": "t1",
+            "title": "Tom & Jerry <1940> | *Remastered*",
+            "link": "https://toloka.to/t1",
+        }));
+
+        assert_eq!(
+            render(&added),
+            "Added: <b><a href=\"https://toloka.to/t679577\">Тед Лассо (Сезон 2) WEB-DL 1080p</a></b>\nSeries, 1.50 GiB"
+        );
+        assert_eq!(
+            render(&updated),
+            "Updated: Тед Лассо S2: episodes 5-6 added\n<b><a href=\"https://toloka.to/t679577\">Тед Лассо (Сезон 2) WEB-DL 1080p</a></b>"
+        );
+        assert_eq!(
+            render(&escaped),
+            "Deleted: <b><a href=\"https://toloka.to/t1\">Tom &amp; Jerry &lt;1940&gt; | *Remastered*</a></b>"
+        );
+    }
+
+    #[test]
+    fn test_renders_alerts() {
+        let failed = notification(serde_json::json!({
+            "kind": "failed",
+            "topic_id": "t1",
+            "title": "Дюна",
+            "link": "https://toloka.to/t1",
+            "attempts": 5,
+            "error": "Torrent <t1> was not added in time",
+        }));
+        let not_enough_space = notification(serde_json::json!({
+            "kind": "not_enough_space",
+            "topic_id": "t1",
+            "title": "Дюна",
+            "link": "https://toloka.to/t1",
+            "required_space": 3 * 1024 * 1024 * 1024_u64,
+            "available_space": 1024 * 1024 * 1024_u64 / 2,
+        }));
+        let pending_deletion = notification(serde_json::json!({
+            "kind": "pending_deletion",
+            "topic_id": "t1",
+            "title": "Дюна",
+            "link": "https://toloka.to/t1",
+            "deleted_in_hours": 72,
+        }));
+        let files_skipped = notification(serde_json::json!({
+            "kind": "files_skipped",
+            "topic_id": "t1",
+            "title": "Дюна",
+            "link": "https://toloka.to/t1",
+            "skipped_files": ["Sample/sample.mkv", "Extras/<making of>.mkv"],
+        }));
+
+        assert_eq!(
+            render(&failed),
+            "Failed: <b><a href=\"https://toloka.to/t1\">Дюна</a></b>\nGave up after 5 attempts. Last error: Torrent &lt;t1&gt; was not added in time"
+        );
+        assert_eq!(
+            render(&not_enough_space),
+            "Not enough free space: <b><a href=\"https://toloka.to/t1\">Дюна</a></b>\n3.00 GiB required, 0.50 GiB available. Will retry on next run."
+        );
+        assert_eq!(
+            render(&pending_deletion),
+            "Not watched anymore: <b><a href=\"https://toloka.to/t1\">Дюна</a></b>\nIts files will be deleted in 72 hours."
+        );
+        assert_eq!(undo_button(&pending_deletion).action, "add_1");
+        assert_eq!(
+            render(&files_skipped),
+            "Skipped files: <b><a href=\"https://toloka.to/t1\">Дюна</a></b>\nSample/sample.mkv\nExtras/&lt;making of&gt;.mkv"
+        );
+    }
+
+    #[test]
+    fn test_renders_blocked_deletions() {
+        let notifications = (0..40)
+            .map(|i| digest_item("deletion_blocked", &format!("t{}", 100 + i)))
+            .collect::<Vec<_>>();
+        let notifications =
+            serde_json::from_value::<Vec<NotificationJson>>(notifications.into()).unwrap();
+
+        let messages = render_deletion_blocked(&notifications, 500);
+
+        assert!(messages.len() > 1);
+        assert!(messages[0].starts_with(
+            "Deletion of 40 topics was blocked, the watched list may be broken. Run <code>sync --allow-mass-deletion</code> to delete them:\n• "
+        ));
+        for message in messages.iter() {
+            assert!(message.chars().count() <= 500);
+        }
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| message.matches("• ").count())
+                .sum::<usize>(),
+            40
+        );
+    }
+
+    fn digest_item(kind: &str, topic_id: &str) -> serde_json::Value {
+        serde_json::json!({
+            "kind": kind,
+            "topic_id": topic_id,
+            "title": format!("Topic {}", topic_id),
+            "link": format!("https://toloka.to/{}", topic_id),
+        })
+    }
+
+    #[test]
+    fn test_renders_digest_sections() {
+        let notifications = serde_json::from_value::<Vec<NotificationJson>>(serde_json::json!([
+            digest_item("deleted", "t3"),
+            {
+                "kind": "updated",
+                "topic_id": "t2",
+                "title": "Tom & Jerry",
+                "category": "Series",
+                "link": "https://toloka.to/t2",
+                "summary": "episodes 5-6 added",
+            },
+            digest_item("added", "t1"),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            render_digest(&notifications, MAX_MESSAGE_LENGTH),
+            vec![[
+                "<b>Added (1)</b>",
+                "• <b><a href=\"https://toloka.to/t1\">Topic t1</a></b>",
+                "",
+                "<b>Updated (1)</b>",
+                "• <b><a href=\"https://toloka.to/t2\">Tom &amp; Jerry</a></b> — episodes 5-6 added, Series",
+                "",
+                "<b>Deleted (1)</b>",
+                "• <b><a href=\"https://toloka.to/t3\">Topic t3</a></b>",
+            ]
+            .join("\n")]
+        );
+        assert!(render_digest(&[], MAX_MESSAGE_LENGTH).is_empty());
+    }
+
+    #[test]
+    fn test_renders_alerts_in_other_section() {
+        let notifications = serde_json::from_value::<Vec<NotificationJson>>(serde_json::json!([
+            {
+                "kind": "pending_deletion",
+                "topic_id": "t2",
+                "title": "Topic t2",
+                "link": "https://toloka.to/t2",
+                "deleted_in_hours": 72,
+            },
+            digest_item("added", "t1"),
+            {
+                "kind": "files_skipped",
+                "topic_id": "t3",
+                "title": "Topic t3",
+                "link": "https://toloka.to/t3",
+                "skipped_files": ["Sample/sample.mkv", "Extras/<making of>.mkv"],
+            },
+            digest_item("restored", "t4"),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            render_digest(&notifications, MAX_MESSAGE_LENGTH),
+            vec![[
+                "<b>Added (1)</b>",
+                "• <b><a href=\"https://toloka.to/t1\">Topic t1</a></b>",
+                "",
+                "<b>Other (3)</b>",
+                "• Not watched anymore: <b><a href=\"https://toloka.to/t2\">Topic t2</a></b> — Its files will be deleted in 72 hours.",
+                "• Restored: <b><a href=\"https://toloka.to/t4\">Topic t4</a></b>",
+                "• Skipped files: <b><a href=\"https://toloka.to/t3\">Topic t3</a></b> — Sample/sample.mkv; Extras/&lt;making of&gt;.mkv",
+            ]
+            .join("\n")]
+        );
+        assert_eq!(digest_undo_button(&notifications[0]).text, "Undo: Topic t2");
+        assert_eq!(digest_undo_button(&notifications[0]).action, "add_2");
+    }
+
+    #[test]
+    fn test_shortens_item_longer_than_message() {
+        let notifications = serde_json::from_value::<Vec<NotificationJson>>(serde_json::json!([
+            {
+                "kind": "added",
+                "topic_id": "t1",
+                "title": "Tom & Jerry ".repeat(50),
+                "category": "Series",
+                "link": "https://toloka.to/t1",
+            },
+            digest_item("added", "t2"),
+        ]))
+        .unwrap();
+
+        let messages = render_digest(&notifications, 200);
+
+        assert_eq!(messages.len(), 2);
+        for message in messages.iter() {
+            assert!(message.chars().count() <= 200);
+            assert!(message.starts_with("<b>Added (2)</b>\n"));
+        }
+        assert!(messages[0].contains("Tom &amp; Jerry Tom &amp; Jerry"));
+        assert!(messages[0].ends_with("…</a></b>"));
+        assert!(!messages[0].contains("Series"));
+    }
+
+    #[test]
+    fn test_splits_long_digest() {
+        let notifications = (0..40)
+            .map(|i| digest_item("updated", &format!("t{}", 100 + i)))
+            .collect::<Vec<_>>();
+        let notifications =
+            serde_json::from_value::<Vec<NotificationJson>>(notifications.into()).unwrap();
+
+        let messages = render_digest(&notifications, 500);
+
+        assert!(messages.len() > 1);
+        for message in messages.iter() {
+            assert!(message.chars().count() <= 500);
+            assert!(message.starts_with("<b>Updated (40)</b>\n"));
+        }
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| message.matches("• ").count())
+                .sum::<usize>(),
+            40
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use actix_web::{web, HttpResponse, Responder};
+use serde_json::Value;
+
+/// Reports of older runs are dropped.
+const MAX_REPORTS: usize = 20;
+
+/// Reports of recent sync runs as sent by the runner, newest first.
+#[derive(Default)]
+pub(crate) struct SyncReports {
+    reports: Mutex<VecDeque<Value>>,
+}
+
+pub(crate) async fn add_report(
+    json: web::Json<Value>,
+    sync_reports: web::Data<SyncReports>,
+) -> impl Responder {
+    let mut reports = sync_reports.reports.lock().unwrap();
+
+    reports.push_front(json.into_inner());
+    reports.truncate(MAX_REPORTS);
+
+    HttpResponse::Ok().finish()
+}
+
+pub(crate) async fn get_reports(sync_reports: web::Data<SyncReports>) -> impl Responder {
+    let reports = sync_reports.reports.lock().unwrap();
+
+    HttpResponse::Ok().json(&*reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App};
+    use serde_json::json;
+
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_reports_round_trip_newest_first() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(SyncReports::default()))
+                .service(
+                    web::resource("/internal/sync-reports")
+                        .route(web::post().to(add_report))
+                        .route(web::get().to(get_reports)),
+                ),
+        )
+        .await;
+        let report = |run: usize| {
+            json!({
+                "started_at": "2024-06-01T00:00:00Z",
+                "finished_at": "2024-06-01T00:01:00Z",
+                "action_counts": { "add": run },
+                "blocked_deletions": 0,
+                "topics": [{ "topic_id": "1", "title": "Topic 1", "action": "add", "error": "404" }],
+                "toloka_time_ms": 1200,
+                "download_client_time_ms": 300,
+                "interrupted": false,
+            })
+        };
+
+        for run in 0..MAX_REPORTS + 2 {
+            let request = test::TestRequest::post()
+                .uri("/internal/sync-reports")
+                .set_json(report(run))
+                .to_request();
+
+            assert!(test::call_service(&app, request)
+                .await
+                .status()
+                .is_success());
+        }
+
+        let request = test::TestRequest::get()
+            .uri("/internal/sync-reports")
+            .to_request();
+        let reports: Vec<Value> = test::call_and_read_body_json(&app, request).await;
+
+        assert_eq!(reports.len(), MAX_REPORTS);
+        assert_eq!(reports[0], report(MAX_REPORTS + 1));
+        assert_eq!(reports[MAX_REPORTS - 1], report(2));
+    }
+}
@@ -1,18 +1,42 @@
 use actix_web::{HttpResponse, Responder, web};
 use serde::Deserialize;
 
-use torrent_bot_clients::telegram::TelegramBotClient;
+use torrent_bot_clients::telegram::{ActionButton, TelegramBotClient};
+
+#[derive(Deserialize)]
+pub(crate) struct ButtonJson {
+    text: String,
+    action: String,
+}
 
 #[derive(Deserialize)]
 pub(crate) struct SendMessageJson {
     text: String,
+    #[serde(default)]
+    buttons: Vec<ButtonJson>,
 }
 
 pub(crate) async fn send_message(
     json: web::Json<SendMessageJson>,
     telegram_bot: web::Data<TelegramBotClient>,
 ) -> impl Responder {
-    telegram_bot.send_message(&json.text).await;
+    let SendMessageJson { text, buttons } = json.into_inner();
+
+    if buttons.is_empty() {
+        telegram_bot.send_message(&text).await;
+    } else {
+        let buttons = buttons
+            .into_iter()
+            .map(|button| ActionButton {
+                text: button.text,
+                action: button.action,
+            })
+            .collect();
+
+        telegram_bot
+            .send_message_with_action_buttons(&text, buttons)
+            .await;
+    }
 
     HttpResponse::Ok().finish()
 }
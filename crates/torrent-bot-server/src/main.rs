@@ -9,6 +9,8 @@ use torrent_bot_clients::telegram::TelegramBotClient;
 use torrent_bot_clients::toloka::TolokaClient;
 
 use crate::config::Config;
+use crate::handlers::events::Events;
+use crate::handlers::sync_reports::SyncReports;
 use crate::telegram_bot::TelegramBot;
 
 mod config;
@@ -41,7 +43,16 @@ async fn main() -> std::io::Result<()> {
 
     let telegram_client =
         TelegramBotClient::create(config.telegram.bot_token, config.telegram.bot_chat_id);
-    let telegram_bot = TelegramBot::create(telegram_client.clone(), toloka_client.clone());
+
+    // Shared between workers, so reports and events sent to any of them can be read back.
+    let sync_reports = Data::new(SyncReports::default());
+    let events = Data::new(Events::default());
+
+    let telegram_bot = TelegramBot::create(
+        telegram_client.clone(),
+        toloka_client.clone(),
+        events.clone(),
+    );
 
     let server = HttpServer::new({
         let telegram_client = telegram_client.clone();
@@ -49,10 +60,34 @@ async fn main() -> std::io::Result<()> {
         move || {
             App::new()
                 .app_data(Data::new(Clone::clone(&telegram_client)))
+                .app_data(sync_reports.clone())
+                .app_data(events.clone())
                 .service(
                     web::resource("/internal/telegram-bot/send-message")
                         .route(web::post().to(handlers::telegram_bot::send_message)),
                 )
+                .service(
+                    web::resource("/internal/notifications")
+                        .route(web::post().to(handlers::notifications::send_notification)),
+                )
+                .service(
+                    web::resource("/internal/notifications/digest")
+                        .route(web::post().to(handlers::notifications::send_digest)),
+                )
+                .service(
+                    web::resource("/internal/notifications/deletion-blocked")
+                        .route(web::post().to(handlers::notifications::send_deletion_blocked)),
+                )
+                .service(
+                    web::resource("/internal/sync-reports")
+                        .route(web::post().to(handlers::sync_reports::add_report))
+                        .route(web::get().to(handlers::sync_reports::get_reports)),
+                )
+                .service(
+                    web::resource("/internal/events")
+                        .route(web::post().to(handlers::events::add_events))
+                        .route(web::get().to(handlers::events::get_events)),
+                )
                 .route(
                     "/health/alive",
                     web::get().to(handlers::readiness_check::readiness_check),
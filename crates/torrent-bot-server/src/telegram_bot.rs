@@ -1,16 +1,34 @@
+use actix_web::web::Data;
+use chrono::Utc;
 use tracing::error;
 
-use torrent_bot_clients::telegram::{ActionButton, BotCommandHandler, TelegramBotClient};
+use torrent_bot_clients::telegram::{
+    ActionButton, BotCommandHandler, TelegramBotClient, MAX_MESSAGE_LENGTH,
+};
 use torrent_bot_clients::toloka::TolokaClient;
 
+use crate::handlers::events::{parse_period, render_events, Events};
+
+/// Period `/events` shows when none is given.
+const DEFAULT_EVENTS_PERIOD: &str = "12h";
+
 pub(crate) struct TelegramBot {
     client: TelegramBotClient,
     toloka: TolokaClient,
+    events: Data<Events>,
 }
 
 impl TelegramBot {
-    pub(crate) fn create(client: TelegramBotClient, toloka: TolokaClient) -> Self {
-        TelegramBot { client, toloka }
+    pub(crate) fn create(
+        client: TelegramBotClient,
+        toloka: TolokaClient,
+        events: Data<Events>,
+    ) -> Self {
+        TelegramBot {
+            client,
+            toloka,
+            events,
+        }
     }
 }
 
@@ -61,4 +79,31 @@ impl BotCommandHandler<'_> for TelegramBot {
             }
         }
     }
+
+    async fn handle_events_command(&self, period: &str) {
+        let period = match period.trim() {
+            "" => DEFAULT_EVENTS_PERIOD,
+            period => period,
+        };
+
+        let Some(since) = parse_period(period).and_then(|ago| Utc::now().checked_sub_signed(ago))
+        else {
+            self.client
+                .send_message("Expected a period like 30m, 12h or 7d.")
+                .await;
+            return;
+        };
+
+        let events = self.events.since(since);
+        if events.is_empty() {
+            self.client
+                .send_message(&format!("Nothing happened in the last {}.", period))
+                .await;
+            return;
+        }
+
+        self.client
+            .send_html_message(&render_events(&events, MAX_MESSAGE_LENGTH))
+            .await;
+    }
 }